@@ -0,0 +1,367 @@
+//! A local agent accepts data points from many clients over a Unix domain socket or a local TCP port,
+//! so clients do not need to connect to Bosun themselves.
+//!
+//! Clients send one data point per line, in OpenTSDB's line format, as JSON object, or in InfluxDB's
+//! line protocol with timestamps in ns, cf. `input::detect_format`, and then close their side of the
+//! connection. The agent then replies with `error line <N>: <REASON>` for each of the first 100
+//! malformed lines, `error <N> more malformed lines` for the rest, and finally with `ok <N>`, the number
+//! of data points accepted. Clients may ignore the reply, e.g.,
+//!
+//! ```bash
+//! echo "backup.runtime $(date +%s) 42 job=daily" | nc -U -q 1 /run/emit_bosun/agent.sock
+//! ```
+//!
+//! The socket only accepts clients its mode permits, by default its owner and group, because the
+//! agent forwards data points to Bosun with its host's credentials. Unix domain sockets are not
+//! available on other platforms; there, the agent only listens on TCP ports.
+//!
+//! The agent may also listen for collectors speaking OpenTSDB's telnet protocol, cf.
+//! `opentsdb::serve_telnet`, or Graphite's plaintext protocol, cf. `graphite::serve`.
+//!
+//! # Example
+//!
+//! ```no_run
+//! use std::time::Duration;
+//! use bosun_emitter::{BosunClient, Datum};
+//! use bosun_emitter::agent::Agent;
+//!
+//! let client = BosunClient::new("localhost:8070", 5);
+//! let mut agent = Agent::new();
+//! agent.listen_unix("/run/emit_bosun/agent.sock", 0o660).unwrap();
+//! loop {
+//!     let batch = agent.next_batch(500, Duration::from_secs(5));
+//!     let data: Vec<Datum> = batch.iter().map(|data_point| data_point.as_datum()).collect();
+//!     if !data.is_empty() {
+//!         let _ = client.emit_data(&data);
+//!     }
+//! }
+//! ```
+
+use std::fs;
+use std::io::{self, BufRead, BufReader, Read, Write};
+#[cfg(unix)]
+use std::net::Shutdown;
+use std::net::{SocketAddr, TcpListener, ToSocketAddrs};
+#[cfg(unix)]
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use super::DataPoint;
+#[cfg(unix)]
+use super::EmitterError;
+use super::graphite::{self, Templates};
+use super::input;
+use super::opentsdb::{self, TelnetStats};
+
+/// Default Unix domain socket of the agent
+pub static DEFAULT_AGENT_SOCKET: &'static str = "/run/emit_bosun/agent.sock";
+
+/// Default mode of the agent's socket; only its owner and group may send data points
+pub static DEFAULT_AGENT_SOCKET_MODE: u32 = 0o660;
+
+/// Malformed lines reported to a client one by one; the others are only counted
+const MAX_REPLY_ERRORS: usize = 100;
+
+/// Collects data points sent by local clients.
+#[derive(Debug)]
+pub struct Agent {
+    sender: Sender<DataPoint>,
+    receiver: Receiver<DataPoint>,
+    /// Socket file to remove when the agent stops
+    socket: Option<PathBuf>,
+}
+
+impl Agent {
+    /// Creates an agent that does not listen yet.
+    pub fn new() -> Agent {
+        let (sender, receiver) = mpsc::channel();
+        Agent {
+            sender: sender,
+            receiver: receiver,
+            socket: None,
+        }
+    }
+
+    /// Listens on a Unix domain socket with the file mode given, e.g., `0o660`.
+    ///
+    /// Fails if another agent listens on the socket already or if the path exists but is not a
+    /// socket; a socket left over by an agent that did not stop properly is replaced.
+    #[cfg(unix)]
+    pub fn listen_unix<P: AsRef<Path>>(&mut self, path: P, mode: u32) -> io::Result<()> {
+        let path = path.as_ref();
+        if let Ok(metadata) = fs::symlink_metadata(path) {
+            if !metadata.file_type().is_socket() {
+                return Err(io::Error::new(io::ErrorKind::AlreadyExists,
+                                          format!("'{}' exists and is not a socket", path.display())));
+            }
+            if UnixStream::connect(path).is_ok() {
+                return Err(io::Error::new(io::ErrorKind::AddrInUse,
+                                          format!("another agent listens on '{}'", path.display())));
+            }
+            try!(fs::remove_file(path));
+        }
+        if let Some(dir) = path.parent() {
+            try!(fs::create_dir_all(dir));
+        }
+        let listener = try!(UnixListener::bind(path));
+        self.socket = Some(path.to_path_buf());
+        try!(fs::set_permissions(path, fs::Permissions::from_mode(mode)));
+
+        let sender = self.sender.clone();
+        thread::spawn(move || for stream in listener.incoming() {
+            match stream.and_then(|s| s.try_clone().map(|r| (r, s))) {
+                Ok((reader, writer)) => spawn_connection(reader, writer, sender.clone()),
+                Err(e) => warn!("Failed to accept agent connection, because {}.", e),
+            }
+        });
+
+        Ok(())
+    }
+
+    /// Fails, because Unix domain sockets are not available on this platform.
+    #[cfg(not(unix))]
+    pub fn listen_unix<P: AsRef<Path>>(&mut self, _path: P, _mode: u32) -> io::Result<()> {
+        Err(io::Error::new(io::ErrorKind::Other, "Unix domain sockets are not supported on this platform"))
+    }
+
+    /// Listens on a TCP address, e.g., `127.0.0.1:4242`, and returns the address bound.
+    pub fn listen_tcp<A: ToSocketAddrs>(&self, address: A) -> io::Result<SocketAddr> {
+        let listener = try!(TcpListener::bind(address));
+        let address = try!(listener.local_addr());
+
+        let sender = self.sender.clone();
+        thread::spawn(move || for stream in listener.incoming() {
+            match stream.and_then(|s| s.try_clone().map(|r| (r, s))) {
+                Ok((reader, writer)) => spawn_connection(reader, writer, sender.clone()),
+                Err(e) => warn!("Failed to accept agent connection, because {}.", e),
+            }
+        });
+
+        Ok(address)
+    }
+
+    /// Listens on a TCP address for OpenTSDB's telnet protocol and returns the address bound.
+    ///
+    /// In contrast to `listen_tcp`, clients may keep their connections open and only receive replies
+    /// on errors and for the commands `version` and `stats`.
+    pub fn listen_telnet<A: ToSocketAddrs>(&self, address: A) -> io::Result<SocketAddr> {
+        let listener = try!(TcpListener::bind(address));
+        let address = try!(listener.local_addr());
+        let stats = Arc::new(TelnetStats::new());
+
+        let sender = self.sender.clone();
+        thread::spawn(move || for stream in listener.incoming() {
+            match stream.and_then(|s| s.try_clone().map(|r| (r, s))) {
+                Ok((reader, writer)) => {
+                    let sender = sender.clone();
+                    let stats = stats.clone();
+                    thread::spawn(move || {
+                        if let Err(e) = opentsdb::serve_telnet(BufReader::new(reader), writer, &sender, &stats) {
+                            debug!("Telnet connection failed, because {}.", e);
+                        }
+                    });
+                }
+                Err(e) => warn!("Failed to accept telnet connection, because {}.", e),
+            }
+        });
+
+        Ok(address)
+    }
+
+    /// Listens on a TCP address for Graphite's plaintext protocol and returns the address bound.
+    ///
+    /// Paths are converted to metric names and tags by the templates.
+    pub fn listen_graphite<A: ToSocketAddrs>(&self, address: A, templates: Templates) -> io::Result<SocketAddr> {
+        let listener = try!(TcpListener::bind(address));
+        let address = try!(listener.local_addr());
+        let templates = Arc::new(templates);
+
+        let sender = self.sender.clone();
+        thread::spawn(move || for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    let sender = sender.clone();
+                    let templates = templates.clone();
+                    thread::spawn(move || {
+                        if let Err(e) = graphite::serve(BufReader::new(stream), &sender, &templates) {
+                            debug!("Graphite connection failed, because {}.", e);
+                        }
+                    });
+                }
+                Err(e) => warn!("Failed to accept Graphite connection, because {}.", e),
+            }
+        });
+
+        Ok(address)
+    }
+
+    /// Returns a sender to pass data points to the agent from other sources.
+    pub fn sender(&self) -> Sender<DataPoint> {
+        self.sender.clone()
+    }
+
+    /// Waits for data points until `max` data points have arrived or `timeout` has passed.
+    pub fn next_batch(&self, max: usize, timeout: Duration) -> Vec<DataPoint> {
+        let deadline = Instant::now() + timeout;
+        let mut batch = Vec::new();
+        while batch.len() < max {
+            let now = Instant::now();
+            let remaining = if deadline > now { deadline - now } else { Duration::from_secs(0) };
+            match self.receiver.recv_timeout(remaining) {
+                Ok(data_point) => batch.push(data_point),
+                Err(RecvTimeoutError::Timeout) |
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+        }
+
+        batch
+    }
+}
+
+impl Default for Agent {
+    fn default() -> Agent {
+        Agent::new()
+    }
+}
+
+impl Drop for Agent {
+    fn drop(&mut self) {
+        if let Some(ref socket) = self.socket {
+            let _ = fs::remove_file(socket);
+        }
+    }
+}
+
+fn spawn_connection<R: Read + Send + 'static, W: Write + Send + 'static>(reader: R,
+                                                                          writer: W,
+                                                                          sender: Sender<DataPoint>) {
+    thread::spawn(move || if let Err(e) = serve(BufReader::new(reader), writer, &sender) {
+        debug!("Agent connection failed, because {}.", e);
+    });
+}
+
+/// Reads data points line by line from a client, passes them on, and replies to the client.
+///
+/// Returns the number of data points accepted. The reply is written after the client has sent all
+/// lines, so clients which do not read while sending never block the agent, nor the agent them.
+///
+/// # Example
+///
+/// ```
+/// # use std::sync::mpsc;
+/// # use bosun_emitter::agent;
+/// let (sender, receiver) = mpsc::channel();
+/// let mut reply = Vec::new();
+/// let input = "backup.runtime 1458066838 42\nbackup.size\n";
+///
+/// assert_eq!(agent::serve(input.as_bytes(), &mut reply, &sender).unwrap(), 1);
+/// assert_eq!(String::from_utf8(reply).unwrap(), "error line 2: missing timestamp\nok 1\n");
+/// assert_eq!(receiver.recv().unwrap().metric, "backup.runtime");
+/// ```
+pub fn serve<R: BufRead, W: Write>(reader: R, mut writer: W, sender: &Sender<DataPoint>) -> io::Result<usize> {
+    let mut accepted = 0;
+    let mut errors = Vec::new();
+    let mut more_errors = 0;
+    for (index, line) in reader.lines().enumerate() {
+        let line = try!(line);
+        match input::parse_line(input::detect_format(&line), &line) {
+            Ok(data) => {
+                for data_point in data {
+                    if sender.send(data_point).is_err() {
+                        return Err(io::Error::new(io::ErrorKind::BrokenPipe, "agent stopped"));
+                    }
+                    accepted += 1;
+                }
+            }
+            Err(_) if errors.len() >= MAX_REPLY_ERRORS => more_errors += 1,
+            Err(e) => errors.push(format!("line {}: {}", index + 1, e)),
+        }
+    }
+    for error in errors {
+        let _ = writeln!(writer, "error {}", error);
+    }
+    if more_errors > 0 {
+        let _ = writeln!(writer, "error {} more malformed lines", more_errors);
+    }
+    let _ = writeln!(writer, "ok {}", accepted);
+
+    Ok(accepted)
+}
+
+/// Reply of an agent to a client
+#[derive(Debug, Clone, PartialEq)]
+pub struct AgentReply {
+    /// Number of data points accepted
+    pub accepted: usize,
+    /// Malformed lines with their line numbers; beyond the first 100, only their number
+    pub errors: Vec<String>,
+}
+
+/// Connection of a client to an agent's Unix domain socket.
+///
+/// # Example
+///
+/// ```no_run
+/// # use bosun_emitter::{DataPoint, Tags};
+/// # use bosun_emitter::agent::AgentClient;
+/// let mut client = AgentClient::connect("/run/emit_bosun/agent.sock").unwrap();
+/// client.send(&DataPoint::new("backup.runtime", 1458066838, "42", Tags::new())).unwrap();
+/// let reply = client.finish().unwrap();
+///
+/// assert_eq!(reply.accepted, 1);
+/// ```
+#[cfg(unix)]
+#[derive(Debug)]
+pub struct AgentClient {
+    stream: UnixStream,
+}
+
+#[cfg(unix)]
+impl AgentClient {
+    /// Connects to an agent.
+    pub fn connect<P: AsRef<Path>>(socket: P) -> Result<AgentClient, EmitterError> {
+        let socket = socket.as_ref();
+        let stream = try!(UnixStream::connect(socket).map_err(|e| {
+            EmitterError::EmitError(format!("cannot connect to agent at '{}', because {}", socket.display(), e))
+        }));
+
+        Ok(AgentClient { stream: stream })
+    }
+
+    /// Sends a data point as JSON line.
+    pub fn send(&mut self, data_point: &DataPoint) -> Result<(), EmitterError> {
+        let json = try!(data_point.as_datum().to_json());
+        try!(writeln!(self.stream, "{}", json));
+
+        Ok(())
+    }
+
+    /// Closes the connection for sending and waits for the agent's reply.
+    pub fn finish(mut self) -> Result<AgentReply, EmitterError> {
+        try!(self.stream.shutdown(Shutdown::Write));
+        let mut reply = String::new();
+        try!(self.stream.read_to_string(&mut reply));
+
+        let mut errors = Vec::new();
+        for line in reply.lines() {
+            if line.starts_with("error ") {
+                errors.push(line["error ".len()..].to_string());
+            } else if line.starts_with("ok ") {
+                if let Ok(accepted) = line["ok ".len()..].parse() {
+                    return Ok(AgentReply {
+                        accepted: accepted,
+                        errors: errors,
+                    });
+                }
+            }
+        }
+
+        Err(EmitterError::EmitError(format!("agent replied unexpectedly '{}'", reply.trim())))
+    }
+}
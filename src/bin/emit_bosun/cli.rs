@@ -0,0 +1,846 @@
+//! Command line interface of emit_bosun, including shell completions and the man page.
+
+use std::env;
+
+use clap::{Arg, ArgMatches, App, AppSettings, Shell, SubCommand};
+use bosun_emitter::{DataPoint, Tags, now_in_ms};
+use bosun_emitter::catalog::DEFAULT_CATALOG_DIR;
+use bosun_emitter::input::Format;
+
+use super::config::{Config, DEFAULT_SCOLLECTOR_CONFIG_FILE, ENV_SETTINGS, SYSTEM_CONFIG_FILE, env_var_name};
+
+static VERSION: &'static str = env!("CARGO_PKG_VERSION");
+pub static BIN_NAME: &'static str = "emit_bosun";
+
+/// Subcommands defined by `build_cli` with their parent subcommands, e.g., for the man page
+static SUBCOMMANDS: &'static [&'static str] = &[
+    "put", "metadata", "metadata sync", "exec", "agent", "statsd", "scrape", "textfile", "import", "check", "config",
+    "config show", "config validate", "completions", "man",
+];
+
+pub fn build_cli() -> App<'static, 'static> {
+    App::new("Emit a Bosun")
+        .version(VERSION)
+        .after_help("Connection options may be passed before or after the subcommand. For compatibility \
+                     with earlier versions, `emit_bosun` still accepts the metric options without a \
+                     subcommand; then it sends a datum with meta data if a value `--value` is passed and \
+                     only meta data otherwise.")
+        .arg(Arg::with_name("config")
+                 .short("c")
+                 .long("config")
+                 .value_name("FILE")
+                 .global(true)
+                 .help("Sets emit_bosun's config file; without a subcommand, scollector's config file like \
+                        earlier versions")
+                 .takes_value(true))
+        .arg(Arg::with_name("scollector-config")
+                 .long("scollector-config")
+                 .value_name("FILE")
+                 .global(true)
+                 .help("Sets scollector config file [default: /etc/bosun/scollector.conf]")
+                 .takes_value(true))
+        .arg(Arg::with_name("host")
+                 .long("host")
+                 .value_name("HOST:PORT")
+                 .global(true)
+                 .help("Sets Bosun server connection parameters; may include basic auth and https")
+                 .takes_value(true))
+        .arg(Arg::with_name("hostname")
+                 .long("hostname")
+                 .value_name("HOSTNAME")
+                 .global(true)
+                 .help("Sets hostname")
+                 .takes_value(true))
+        .arg(Arg::with_name("full-host")
+                 .long("full-host")
+                 .conflicts_with("hostname")
+                 .global(true)
+                 .help("Uses the fully qualified hostname if the hostname is detected automatically"))
+        .arg(Arg::with_name("timeout")
+                 .long("timeout")
+                 .value_name("TIME OUT")
+                 .default_value("5")
+                 .validator(arg_is_u64)
+                 .global(true)
+                 .help("Sets timeout in sec [default=5]")
+                 .takes_value(true))
+        .args(&tag_args())
+        .arg(Arg::with_name("force-metadata")
+                 .long("force-metadata")
+                 .global(true)
+                 .help("Sends meta data even if it has been sent recently"))
+        .arg(Arg::with_name("dry-run")
+                 .long("dry-run")
+                 .global(true)
+                 .help("Prints the requests instead of sending them"))
+        .arg(Arg::with_name("output")
+                 .long("output")
+                 .value_name("FORMAT")
+                 .possible_values(&["text", "json", "scollector"])
+                 .global(true)
+                 .help("Sets output format; json reports the requests sent, their latency, and errors; scollector \
+                        prints data and meta data for scollector's external collectors instead of sending them \
+                        [default: text]")
+                 .takes_value(true))
+        .arg(Arg::with_name("verbose")
+                 .long("verbose")
+                 .global(true)
+                 .help("Enables verbose output on stderr"))
+        .arg(metric_arg("Sets metric name"))
+        .arg(value_arg())
+        .arg(timestamp_arg())
+        .args(&metadata_args())
+        .arg(Arg::with_name("show-config")
+                 .long("show-config")
+                 .help("Prints config"))
+        .arg(Arg::with_name("force")
+                 .hidden(true)
+                 .long("force")
+                 .help("Forces metric datum to be send even without meta data"))
+        .subcommand(SubCommand::with_name("put")
+                        .about("Sends a datum, or data points read from stdin")
+                        .arg(metric_arg("Sets metric name; repeat as NAME=VALUE followed by its meta data to send multiple metrics")
+                                 .multiple(true)
+                                 .number_of_values(1)
+                                 .conflicts_with("stdin"))
+                        .arg(value_arg().conflicts_with("stdin"))
+                        .arg(timestamp_arg().conflicts_with("stdin"))
+                        .args(&metadata_args().into_iter().map(|arg| arg.multiple(true).number_of_values(1)).collect::<Vec<_>>())
+                        .arg(Arg::with_name("stdin")
+                                 .long("stdin")
+                                 .help("Reads data points line by line from stdin"))
+                        .arg(Arg::with_name("format")
+                                 .long("format")
+                                 .value_name("FORMAT")
+                                 .possible_values(&["opentsdb", "json", "graphite", "influx"])
+                                 .requires("stdin")
+                                 .help("Sets input format, i.e., 'metric timestamp value tagk=tagv ...', JSON objects, \
+                                        'path value timestamp', or InfluxDB's line protocol [default: opentsdb]")
+                                 .takes_value(true))
+                        .arg(Arg::with_name("precision")
+                                 .long("precision")
+                                 .value_name("PRECISION")
+                                 .possible_values(&["ns", "us", "ms", "s"])
+                                 .requires("stdin")
+                                 .help("Sets the unit of InfluxDB timestamps [default: ns]")
+                                 .takes_value(true))
+                        .arg(graphite_template_arg())
+                        .arg(Arg::with_name("via-agent")
+                                 .long("via-agent")
+                                 .help("Passes data points to the local agent instead of sending them to Bosun"))
+                        .args(&tag_args()))
+        .subcommand(SubCommand::with_name("metadata")
+                        .about("Sends meta data")
+                        .arg(metric_arg("Sets metric name"))
+                        .args(&metadata_args())
+                        .args(&tag_args())
+                        .subcommand(SubCommand::with_name("sync")
+                                        .about("Sends the meta data of all metrics declared in the catalog")
+                                        .args(&tag_args())))
+        .subcommand(SubCommand::with_name("exec")
+                        .about("Runs a command and sends its runtime, exit code, and success")
+                        .after_help("Exits with the command's exit code. If the command succeeds but its metrics \
+                                     cannot be sent, exits with the exit code of the failure to send instead.")
+                        .setting(AppSettings::TrailingVarArg)
+                        .arg(metric_arg("Sets metric name prefix for <METRIC NAME>.runtime, <METRIC NAME>.exit_code, and <METRIC NAME>.success"))
+                        .arg(Arg::with_name("rusage")
+                                 .long("rusage")
+                                 .help("Also sends peak memory usage <METRIC NAME>.max_rss and CPU time <METRIC NAME>.cpu_time"))
+                        .args(&tag_args())
+                        .arg(Arg::with_name("command")
+                                 .value_name("COMMAND")
+                                 .required(true)
+                                 .multiple(true)
+                                 .help("Command to run with its arguments")))
+        .subcommand(SubCommand::with_name("agent")
+                        .about("Receives data points from local clients and sends them in batches")
+                        .after_help("Clients send one data point per line in OpenTSDB's line format, as JSON object, or in \
+                                     InfluxDB's line protocol with timestamps in ns, e.g., \
+                                     by `emit_bosun put --via-agent`. The agent adds the metric prefix and the configured \
+                                     tags, sends the catalog's meta data, and spools data points it cannot send. It stops \
+                                     on SIGTERM or SIGINT after sending all data points received. Collectors that send to \
+                                     OpenTSDB's telnet interface may send to the telnet port instead. The TCP ports are only \
+                                     opened if set, and any local user may send data points to them, regardless of the \
+                                     socket's mode.")
+                        .arg(Arg::with_name("socket")
+                                 .long("socket")
+                                 .value_name("FILE")
+                                 .help("Sets the Unix domain socket to listen on [default: /run/emit_bosun/agent.sock]")
+                                 .takes_value(true))
+                        .arg(Arg::with_name("socket-mode")
+                                 .long("socket-mode")
+                                 .value_name("MODE")
+                                 .help("Sets the octal file mode of the socket, i.e., who may send data points [default: 660]")
+                                 .takes_value(true))
+                        .arg(Arg::with_name("port")
+                                 .long("port")
+                                 .value_name("PORT")
+                                 .help("Also listens on this TCP port of localhost, which any local user may send to")
+                                 .takes_value(true))
+                        .arg(Arg::with_name("telnet-port")
+                                 .long("telnet-port")
+                                 .value_name("PORT")
+                                 .help("Also accepts OpenTSDB's telnet protocol on this TCP port of localhost, e.g., 4242, \
+                                        which any local user may send to")
+                                 .takes_value(true))
+                        .arg(Arg::with_name("graphite-port")
+                                 .long("graphite-port")
+                                 .value_name("PORT")
+                                 .help("Also accepts Graphite's plaintext protocol on this TCP port of localhost, e.g., 2003, \
+                                        which any local user may send to")
+                                 .takes_value(true))
+                        .arg(graphite_template_arg())
+                        .arg(Arg::with_name("flush-interval")
+                                 .long("flush-interval")
+                                 .value_name("SEC")
+                                 .help("Sets how long to collect data points before sending them [default: 5]")
+                                 .takes_value(true))
+                        .args(&tag_args()))
+        .subcommand(SubCommand::with_name("statsd")
+                        .about("Receives StatsD metrics via UDP and sends their aggregates")
+                        .after_help("Receives counters (c), timers (ms), histograms (h), gauges (g), and sets (s) with \
+                                     sample rates and DogStatsD tags. Each flush interval, it sends counters as \
+                                     <METRIC>.count and <METRIC>.rate; timers and histograms as <METRIC>.count, .min, \
+                                     .max, .mean, and a percentile like .p90; gauges as <METRIC>; and sets as \
+                                     <METRIC>.count together with their meta data. It stops on SIGTERM or SIGINT after \
+                                     sending the samples received.")
+                        .arg(Arg::with_name("address")
+                                 .long("address")
+                                 .value_name("HOST:PORT")
+                                 .help("Sets the UDP address to listen on [default: 127.0.0.1:8125]")
+                                 .takes_value(true))
+                        .arg(Arg::with_name("flush-interval")
+                                 .long("flush-interval")
+                                 .value_name("SEC")
+                                 .help("Sets how long to aggregate samples before sending them [default: 10]")
+                                 .takes_value(true))
+                        .arg(Arg::with_name("percentiles")
+                                 .long("percentiles")
+                                 .value_name("P1,P2,...")
+                                 .help("Sets the percentiles to send for timers [default: 90,95,99]")
+                                 .takes_value(true))
+                        .args(&tag_args()))
+        .subcommand(SubCommand::with_name("scrape")
+                        .about("Scrapes Prometheus metrics endpoints or files and sends their samples")
+                        .after_help("Reads Prometheus' text exposition format from URLs, files, or stdin as '-'. Samples \
+                                     keep their metric names and get meta data from # HELP and # TYPE; histograms and \
+                                     summaries are sent as their _bucket, _sum, and _count samples and quantiles. Labels \
+                                     become tags unless a rule renames or drops them, e.g., \
+                                     `emit_bosun scrape --label instance=host --label -job http://localhost:9100/metrics`.")
+                        .arg(Arg::with_name("label")
+                                 .long("label")
+                                 .value_name("LABEL=TAG|-LABEL")
+                                 .allow_hyphen_values(true)
+                                 .multiple(true)
+                                 .number_of_values(1)
+                                 .help("Renames a label's tag or drops a label; may be repeated")
+                                 .takes_value(true))
+                        .arg(Arg::with_name("source")
+                                 .value_name("URL|FILE")
+                                 .required(true)
+                                 .multiple(true)
+                                 .help("Endpoints and files to scrape"))
+                        .args(&tag_args()))
+        .subcommand(SubCommand::with_name("textfile")
+                        .about("Reads metrics files written by other programs into a directory and sends them")
+                        .after_help("Reads all files of the directory, e.g., written by cron jobs. Files ending with .prom \
+                                     are read in Prometheus' text exposition format including their meta data, all other \
+                                     files in OpenTSDB's line format, as JSON objects, or in InfluxDB's line protocol \
+                                     with timestamps in ns. Hidden files and files ending with .tmp or ~ are skipped, so \
+                                     writers should write to a temporary file and rename it. With --file-metrics, it \
+                                     also sends emit_bosun.textfile.mtime, .age, and .errors per file tagged with file \
+                                     to alert on jobs that stopped writing.")
+                        .arg(Arg::with_name("dir")
+                                 .long("dir")
+                                 .value_name("DIR")
+                                 .help("Sets the directory to read [default: /var/lib/bosun/textfile]")
+                                 .takes_value(true))
+                        .arg(Arg::with_name("file-metrics")
+                                 .long("file-metrics")
+                                 .help("Also sends the modification time, age, and number of malformed lines of each file"))
+                        .arg(Arg::with_name("label")
+                                 .long("label")
+                                 .value_name("LABEL=TAG|-LABEL")
+                                 .allow_hyphen_values(true)
+                                 .multiple(true)
+                                 .number_of_values(1)
+                                 .help("Renames a label's tag or drops a label of .prom files; may be repeated")
+                                 .takes_value(true))
+                        .args(&tag_args()))
+        .subcommand(SubCommand::with_name("import")
+                        .about("Imports data points from CSV or JSON files, e.g., to backfill historical data")
+                        .after_help("CSV files start with a header; columns are referred to by name or by number \
+                                     starting at 1. JSON files contain one object per line; columns are their keys. \
+                                     Data points are sent in batches like `put --stdin`, but failed batches are not \
+                                     spooled; instead, the import stops and reports the line to resume from, e.g., \
+                                     `emit_bosun import --metric backup.size --timestamp-column date --timestamp-format \
+                                     %Y-%m-%d --tag-column job=type backups.csv`.")
+                        .arg(Arg::with_name("format")
+                                 .long("format")
+                                 .value_name("FORMAT")
+                                 .possible_values(&["csv", "json"])
+                                 .default_value("csv")
+                                 .help("Sets input format")
+                                 .takes_value(true))
+                        .arg(Arg::with_name("delimiter")
+                                 .long("delimiter")
+                                 .value_name("CHAR")
+                                 .help("Sets the delimiter of CSV files; \\t for tabs [default: ,]")
+                                 .takes_value(true))
+                        .arg(Arg::with_name("metric")
+                                 .long("metric")
+                                 .value_name("NAME")
+                                 .conflicts_with("metric-column")
+                                 .help("Sets the metric name of all data points")
+                                 .takes_value(true))
+                        .arg(Arg::with_name("metric-column")
+                                 .long("metric-column")
+                                 .value_name("COLUMN")
+                                 .help("Sets the column of metric names [default: metric]")
+                                 .takes_value(true))
+                        .arg(Arg::with_name("timestamp-column")
+                                 .long("timestamp-column")
+                                 .value_name("COLUMN")
+                                 .help("Sets the column of timestamps [default: timestamp]")
+                                 .takes_value(true))
+                        .arg(Arg::with_name("timestamp-format")
+                                 .long("timestamp-format")
+                                 .value_name("FORMAT")
+                                 .help("Parses timestamps by a strftime format like '%Y-%m-%d %H:%M:%S' in UTC unless \
+                                        it contains %z [default: Unix seconds or ms, or RFC 3339]")
+                                 .takes_value(true))
+                        .arg(Arg::with_name("value-column")
+                                 .long("value-column")
+                                 .value_name("COLUMN")
+                                 .help("Sets the column of values [default: value]")
+                                 .takes_value(true))
+                        .arg(Arg::with_name("tag-column")
+                                 .long("tag-column")
+                                 .value_name("COLUMN[=TAG]")
+                                 .multiple(true)
+                                 .number_of_values(1)
+                                 .help("Sends a column as tag, named like the column unless TAG is given; may be repeated")
+                                 .takes_value(true))
+                        .arg(Arg::with_name("rate-limit")
+                                 .long("rate-limit")
+                                 .value_name("DATA POINTS/SEC")
+                                 .validator(arg_is_u64)
+                                 .help("Limits how many data points are sent per second")
+                                 .takes_value(true))
+                        .arg(Arg::with_name("resume-from")
+                                 .long("resume-from")
+                                 .value_name("LINE")
+                                 .validator(arg_is_u64)
+                                 .help("Skips the records before a line, e.g., to resume a failed import")
+                                 .takes_value(true))
+                        .arg(Arg::with_name("file")
+                                 .value_name("FILE")
+                                 .required(true)
+                                 .help("File to import, or - for stdin"))
+                        .args(&tag_args()))
+        .subcommand(SubCommand::with_name("check")
+                        .about("Checks the configuration and whether Bosun is reachable")
+                        .args(&tag_args()))
+        .subcommand(SubCommand::with_name("config")
+                        .about("Inspects the configuration")
+                        .setting(AppSettings::SubcommandRequiredElseHelp)
+                        .args(&tag_args())
+                        .subcommand(SubCommand::with_name("show")
+                                        .about("Prints the configuration with secrets masked and the source of each value")
+                                        .arg(Arg::with_name("format")
+                                                 .long("format")
+                                                 .value_name("FORMAT")
+                                                 .possible_values(&["toml", "json"])
+                                                 .default_value("toml")
+                                                 .help("Sets output format")
+                                                 .takes_value(true))
+                                        .args(&tag_args()))
+                        .subcommand(SubCommand::with_name("validate")
+                                        .about("Checks the configuration and reports all problems")
+                                        .args(&tag_args())))
+        .subcommand(SubCommand::with_name("completions")
+                        .about("Prints a completion script for bash, zsh, or fish")
+                        .after_help("Metric names are completed from the catalog and the meta data cache, e.g., \
+                                     `emit_bosun completions bash > /etc/bash_completion.d/emit_bosun`.")
+                        .arg(Arg::with_name("shell")
+                                 .value_name("SHELL")
+                                 .possible_values(&["bash", "zsh", "fish"])
+                                 .required_unless("metrics")
+                                 .help("Shell to complete for"))
+                        .arg(Arg::with_name("metrics")
+                                 .long("metrics")
+                                 .hidden(true)
+                                 .conflicts_with("shell")
+                                 .help("Prints the metric names to complete")))
+        .subcommand(SubCommand::with_name("man")
+                        .about("Prints the man page, e.g., `emit_bosun man > emit_bosun.1`"))
+}
+
+/// Returns the input format of `put --stdin`.
+pub fn input_format(put_args: &ArgMatches) -> Format {
+    // unwraps are safe, because clap checks possible values
+    match put_args.value_of("format").unwrap_or("opentsdb").parse().unwrap() {
+        Format::Influx(_) => Format::Influx(put_args.value_of("precision").unwrap_or("ns").parse().unwrap()),
+        format => format,
+    }
+}
+
+/// Graphite template option of subcommands reading Graphite's plaintext protocol.
+fn graphite_template_arg() -> Arg<'static, 'static> {
+    Arg::with_name("graphite-template")
+        .long("graphite-template")
+        .value_name("[FILTER] TEMPLATE [TAGS]")
+        .multiple(true)
+        .number_of_values(1)
+        .help("Converts Graphite paths to metric names and tags, e.g., 'servers.*.cpu.* .host.measurement.field'; \
+               may be repeated")
+        .takes_value(true)
+}
+
+/// Tag options; not global, because clap keeps only the subcommand's values of global options, but
+/// all tags passed before and after a subcommand apply.
+fn tag_args() -> Vec<Arg<'static, 'static>> {
+    vec![Arg::with_name("tags")
+             .use_delimiter(false)
+             .short("t")
+             .long("tags")
+             .value_name("KEY1=VALUE1,KEY2=VALUE2,...")
+             .allow_hyphen_values(true)
+             .help("Sets tags; -KEY removes a tag, \\ escapes and double quotes quote , and =")
+             .takes_value(true),
+         Arg::with_name("tag")
+             .long("tag")
+             .value_name("KEY=VALUE")
+             .multiple(true)
+             .number_of_values(1)
+             .allow_hyphen_values(true)
+             .help("Sets a tag, or removes it as -KEY; may be repeated")
+             .takes_value(true)]
+}
+
+fn metric_arg(help: &'static str) -> Arg<'static, 'static> {
+    Arg::with_name("metric")
+        .short("m")
+        .long("metric")
+        .value_name("METRIC NAME")
+        .help(help)
+        .takes_value(true)
+}
+
+fn value_arg() -> Arg<'static, 'static> {
+    Arg::with_name("value")
+        .requires("metric")
+        .short("v")
+        .long("value")
+        .value_name("VALUE")
+        .help("Sets metric value")
+        .takes_value(true)
+}
+
+fn timestamp_arg() -> Arg<'static, 'static> {
+    Arg::with_name("timestamp")
+        .requires("metric")
+        .long("timestamp")
+        .allow_hyphen_values(true)
+        .value_name("TIMESTAMP")
+        .help("Sets timestamp of the datum as Unix seconds or ms, RFC 3339, or relative like -1h [default: now]")
+        .takes_value(true)
+}
+
+fn metadata_args() -> Vec<Arg<'static, 'static>> {
+    vec![Arg::with_name("rate")
+             .requires_all(&["metric", "rate", "unit", "description"])
+             .short("r")
+             .long("rate")
+             .value_name("RATE")
+             .possible_values(&["gauge", "counter", "rate"])
+             .help("Sets rate type")
+             .takes_value(true),
+         Arg::with_name("unit")
+             .requires_all(&["metric", "rate", "unit", "description"])
+             .short("u")
+             .long("unit")
+             .value_name("UNIT")
+             .help("Sets metric value unit")
+             .takes_value(true),
+         Arg::with_name("description")
+             .requires_all(&["metric", "rate", "unit", "description"])
+             .short("d")
+             .long("description")
+             .value_name("DESCRIPTION")
+             .help("Sets metric description")
+             .takes_value(true)]
+}
+
+/// A metric passed as `--metric NAME=VALUE` together with the meta data options following it.
+#[derive(Debug, PartialEq)]
+pub struct MetricGroup {
+    pub metric: String,
+    pub value: String,
+    pub rate: Option<String>,
+    pub unit: Option<String>,
+    pub description: Option<String>,
+}
+
+/// Checks if metrics are passed as `--metric NAME=VALUE` groups instead of `--metric NAME --value VALUE`.
+pub fn is_metric_groups(args: &ArgMatches) -> bool {
+    args.occurrences_of("metric") > 1 || args.values_of("metric").map(|mut ms| ms.any(|m| m.contains('='))).unwrap_or(false)
+}
+
+/// Assigns each meta data option to the preceding `--metric NAME=VALUE`.
+pub fn metric_groups(args: &ArgMatches) -> Result<Vec<MetricGroup>, String> {
+    if args.is_present("value") {
+        return Err("--value cannot be combined with multiple metrics; use --metric NAME=VALUE".to_string());
+    }
+    // unwraps are safe, because clap records an index for each value
+    let metrics = args.values_of("metric").unwrap().zip(args.indices_of("metric").unwrap());
+    let mut groups: Vec<(usize, MetricGroup)> = Vec::new();
+    for (metric, index) in metrics {
+        let mut parts = metric.splitn(2, '=');
+        let (name, value) = match (parts.next(), parts.next()) {
+            (Some(name), Some(value)) if !name.is_empty() && !value.is_empty() => (name, value),
+            _ => return Err(format!("metric '{}' is not NAME=VALUE", metric)),
+        };
+        try!(DataPoint::new(name, now_in_ms(), value, Tags::new())
+            .validate()
+            .map_err(|e| format!("metric '{}' is invalid, because {}", name, e)));
+        let group = MetricGroup {
+            metric: name.to_string(),
+            value: value.to_string(),
+            rate: None,
+            unit: None,
+            description: None,
+        };
+        groups.push((index, group));
+    }
+
+    for option in &["rate", "unit", "description"] {
+        let values = match (args.values_of(option), args.indices_of(option)) {
+            (Some(values), Some(indices)) => values.zip(indices),
+            _ => continue,
+        };
+        for (value, index) in values {
+            let group = match groups.iter_mut().rev().find(|&&mut (metric_index, _)| metric_index < index) {
+                Some(&mut (_, ref mut group)) => group,
+                None => return Err(format!("--{} '{}' does not follow a metric", option, value)),
+            };
+            let field = match *option {
+                "rate" => &mut group.rate,
+                "unit" => &mut group.unit,
+                _ => &mut group.description,
+            };
+            if field.is_some() {
+                return Err(format!("--{} is set twice for metric '{}'", option, group.metric));
+            }
+            *field = Some(value.to_string());
+        }
+    }
+
+    let groups: Vec<MetricGroup> = groups.into_iter().map(|(_, group)| group).collect();
+    for group in &groups {
+        let metadata_count = [&group.rate, &group.unit, &group.description].iter().filter(|o| o.is_some()).count();
+        if metadata_count != 0 && metadata_count != 3 {
+            return Err(format!("meta data of metric '{}' requires rate, unit, and description", group.metric));
+        }
+    }
+
+    Ok(groups)
+}
+
+/// Returns the metric names known from the catalog and the meta data cache without the metric prefix.
+pub fn completion_metrics(config: &Config) -> Vec<String> {
+    let prefix = config.metric_prefix.as_ref().map(|_| config.prefixed(""));
+    let mut metrics: Vec<String> = config.catalog
+        .metadata()
+        .iter()
+        .map(|m| m.metric.to_string())
+        .chain(config.client().metadata_cache.metrics())
+        .map(|metric| match prefix {
+            Some(ref prefix) if metric.starts_with(prefix.as_str()) => metric[prefix.len()..].to_string(),
+            _ => metric,
+        })
+        .collect();
+    metrics.sort();
+    metrics.dedup();
+
+    metrics
+}
+
+/// Generates the completion script of a shell, which completes metric names by calling
+/// `emit_bosun completions --metrics`.
+///
+/// The script generated by clap is kept as is; metric names are completed by a separate function
+/// that takes over the completion of `--metric` and hands everything else to clap's function. If
+/// the script does not look as expected, it is returned without metric completion.
+pub fn completions(shell: Shell) -> String {
+    let mut script = Vec::new();
+    build_cli().gen_completions_to(BIN_NAME, shell, &mut script);
+    let mut script = String::from_utf8_lossy(&script).trim_end().to_string() + "\n";
+    let list_metrics = format!("{} completions --metrics 2>/dev/null", BIN_NAME);
+
+    match shell {
+        Shell::Bash if script.lines().any(|line| line == format!("_{}() {{", BIN_NAME)) => {
+            script.push_str(&format!("\n_{0}_metrics() {{\n    \
+                                      case \"${{COMP_WORDS[COMP_CWORD-1]}}\" in\n        \
+                                      -m|--metric)\n            \
+                                      COMPREPLY=($(compgen -W \"$({1})\" -- \"${{COMP_WORDS[COMP_CWORD]}}\"))\n            \
+                                      ;;\n        \
+                                      *)\n            \
+                                      _{0} \"$@\"\n            \
+                                      ;;\n    \
+                                      esac\n\
+                                      }}\n\n\
+                                      complete -F _{0}_metrics -o bashdefault -o default {0}\n",
+                                     BIN_NAME,
+                                     list_metrics));
+        }
+        Shell::Zsh => {
+            // The script ends by calling clap's function, so that call is replaced by one of the
+            // metric function, which also takes over the completion of later command lines
+            let call = format!("\n_{} \"$@\"\n", BIN_NAME);
+            if script.ends_with(&call) {
+                let len = script.len() - call.len() + 1;
+                script.truncate(len);
+                script.push_str(&format!("_{0}_metrics() {{\n    \
+                                          if [[ $words[CURRENT-1] == (-m|--metric) ]] || compset -P '--metric='; then\n        \
+                                          local metrics; metrics=(${{(f)\"$({1})\"}})\n        \
+                                          compadd -a metrics\n    \
+                                          else\n        \
+                                          _{0} \"$@\"\n    \
+                                          fi\n\
+                                          }}\n\n\
+                                          compdef _{0}_metrics {0}\n\
+                                          _{0}_metrics \"$@\"\n",
+                                         BIN_NAME,
+                                         list_metrics));
+            }
+        }
+        Shell::Fish => {
+            script.push_str(&format!("complete -c {} -s m -l metric -r -f -a \"({})\"\n", BIN_NAME, list_metrics));
+        }
+        _ => {}
+    }
+
+    script
+}
+
+/// Renders the man page from the help of emit_bosun and all its subcommands.
+pub fn man_page() -> String {
+    let mut page = format!(".TH EMIT_BOSUN 1 \"\" \"{} {}\" \"User Commands\"\n", BIN_NAME, VERSION);
+    page.push_str(&format!(".SH NAME\n{} \\- {}", BIN_NAME, roff_escape(env!("CARGO_PKG_DESCRIPTION"))));
+    page.push_str(&format!(".SH SYNOPSIS\n.B {}\n[OPTIONS] [SUBCOMMAND]\n", BIN_NAME));
+    page.push_str(&format!(".SH DESCRIPTION\n.nf\n{}.fi\n", roff_escape(&help_text(&[]))));
+
+    page.push_str(".SH SUBCOMMANDS\n");
+    for command in SUBCOMMANDS {
+        let help = help_text(&command.split(' ').map(|c| c.to_string()).collect::<Vec<_>>());
+        page.push_str(&format!(".SS \"{} {}\"\n.nf\n{}.fi\n", BIN_NAME, command, roff_escape(&help)));
+    }
+
+    page.push_str(".SH ENVIRONMENT\n");
+    for setting in ENV_SETTINGS {
+        page.push_str(&format!(".TP\n.B {}\nSets {}.\n", env_var_name(setting), setting));
+    }
+    page.push_str(".TP\n.B BOSUN_VERBOSE\nEnables verbose output.\n");
+
+    page.push_str(".SH FILES\n");
+    for &(file, description) in &[(SYSTEM_CONFIG_FILE, "System configuration"),
+                                  ("~/.config/emit_bosun/config.toml", "User configuration"),
+                                  (DEFAULT_SCOLLECTOR_CONFIG_FILE, "scollector configuration"),
+                                  (DEFAULT_CATALOG_DIR, "Catalog of metric meta data"),
+                                  ("~/.cache/emit_bosun/metadata", "Meta data cache")] {
+        page.push_str(&format!(".TP\n.I {}\n{}\n", file, description));
+    }
+
+    page
+}
+
+/// Returns the help of a subcommand, e.g., `["metadata", "sync"]`, without the leading name and version.
+fn help_text(command: &[String]) -> String {
+    let args: Vec<&str> = Some(BIN_NAME)
+        .into_iter()
+        .chain(command.iter().map(|c| c.as_str()))
+        .chain(Some("--help"))
+        .collect();
+    let help = match build_cli().set_term_width(80).get_matches_from_safe(args) {
+        Err(err) => err.message,
+        Ok(_) => String::new(),
+    };
+
+    help.lines().skip(1).skip_while(|line| line.is_empty()).map(|line| format!("{}\n", line)).collect()
+}
+
+/// Escapes text for roff, so lines are not taken as requests and backslashes and hyphens are kept.
+fn roff_escape(text: &str) -> String {
+    let mut escaped = String::new();
+    for line in text.lines() {
+        let line = line.replace('\\', "\\e").replace('-', "\\-");
+        if line.starts_with('.') || line.starts_with('\'') {
+            escaped.push_str("\\&");
+        }
+        escaped.push_str(&line);
+        escaped.push('\n');
+    }
+
+    escaped
+}
+
+fn arg_is_u64(v: String) -> Result<(), String> {
+    v.parse::<u64>()
+        .map(|_| ())
+        .map_err(|_| String::from("must be a number"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{MetricGroup, SUBCOMMANDS, build_cli, completion_metrics, completions, input_format, man_page, metric_groups};
+    use config::Config;
+    use clap::Shell;
+    use bosun_emitter::catalog::Catalog;
+    use bosun_emitter::influx::Precision;
+    use bosun_emitter::input::Format;
+
+    #[test]
+    fn influx_format_honors_precision() {
+        let cli_args = build_cli().get_matches_from(vec!["emit_bosun", "put", "--stdin", "--format", "influx", "--precision", "s"]);
+        assert_eq!(input_format(cli_args.subcommand_matches("put").unwrap()), Format::Influx(Precision::Seconds));
+
+        let cli_args = build_cli().get_matches_from(vec!["emit_bosun", "put", "--stdin", "--format", "influx"]);
+        assert_eq!(input_format(cli_args.subcommand_matches("put").unwrap()), Format::Influx(Precision::Nanoseconds));
+
+        let cli_args = build_cli().get_matches_from(vec!["emit_bosun", "put", "--stdin", "--precision", "s"]);
+        assert_eq!(input_format(cli_args.subcommand_matches("put").unwrap()), Format::OpenTsdb);
+    }
+
+    #[test]
+    fn completions_complete_metric_names_and_rates() {
+        let bash = completions(Shell::Bash);
+        assert!(bash.starts_with("_emit_bosun() {\n"));
+        assert!(bash.contains("-m|--metric)\n            COMPREPLY=($(compgen -W \"$(emit_bosun completions --metrics 2>/dev/null)\""));
+        assert!(bash.ends_with("\ncomplete -F _emit_bosun_metrics -o bashdefault -o default emit_bosun\n"));
+        assert!(bash.contains("compgen -W \"gauge counter rate\""));
+
+        let zsh = completions(Shell::Zsh);
+        assert!(zsh.contains("\n_emit_bosun_metrics() {\n"));
+        assert!(zsh.ends_with("\ncompdef _emit_bosun_metrics emit_bosun\n_emit_bosun_metrics \"$@\"\n"));
+        assert!(!zsh.contains("\n_emit_bosun \"$@\"\n"));
+
+        let fish = completions(Shell::Fish);
+        assert!(fish.ends_with("\ncomplete -c emit_bosun -s m -l metric -r -f -a \"(emit_bosun completions --metrics 2>/dev/null)\"\n"));
+    }
+
+    #[test]
+    fn completion_metrics_lists_catalog_metrics_without_prefix() {
+        let mut config = Config::default();
+        config.metadata_cache = None;
+        config.metric_prefix = Some("backup".to_string());
+        config.catalog.add(Catalog::parse(r#"
+            [[metric]]
+            name = "backup.runtime"
+            rate = "gauge"
+            unit = "sec"
+            description = "Runtime"
+
+            [[metric]]
+            name = "backup.*.bytes"
+            rate = "gauge"
+            unit = "bytes"
+            description = "Bytes written"
+
+            [[metric]]
+            name = "os.cpu"
+            rate = "counter"
+            unit = "sec"
+            description = "CPU time"
+        "#).unwrap());
+
+        assert_eq!(completion_metrics(&config), vec!["os.cpu".to_string(), "runtime".to_string()]);
+    }
+
+    #[test]
+    fn man_page_describes_all_subcommands() {
+        let man_page = man_page();
+
+        assert!(man_page.starts_with(".TH EMIT_BOSUN 1"));
+        assert!(man_page.contains(".SS \"emit_bosun metadata sync\"\n.nf\nSends the meta data of all metrics declared in the catalog\n"));
+        assert!(man_page.contains("\\-r, \\-\\-rate <RATE>"));
+        assert!(man_page.contains(".B BOSUN_METADATA_TTL\n"));
+        assert!(!man_page.contains("emit_bosun help"));
+        for command in SUBCOMMANDS {
+            assert!(man_page.contains(&format!(".SS \"emit_bosun {}\"\n", command)), "{} is missing", command);
+        }
+    }
+
+    #[test]
+    fn subcommands_list_all_subcommands_defined() {
+        // The fish completion lists each subcommand with its parent as `-n "<condition>" -f -a "<name>"`
+        let fish = completions(Shell::Fish);
+        let mut defined: Vec<String> = fish.lines()
+            .filter_map(|line| {
+                let parent = if line.contains("-n \"__fish_use_subcommand\" -f -a \"") {
+                    ""
+                } else if line.contains("-n \"__fish_seen_subcommand_from ") && line.contains("\" -f -a \"") {
+                    line.split("__fish_seen_subcommand_from ").nth(1).and_then(|rest| rest.split('"').next()).unwrap_or("")
+                } else {
+                    return None;
+                };
+                let name = line.split(" -f -a \"").nth(1).and_then(|rest| rest.split('"').next()).unwrap_or("");
+                Some(format!("{} {}", parent, name).trim().to_string())
+            })
+            .filter(|command| !command.ends_with("help"))
+            .collect();
+
+        defined.sort();
+        let mut listed: Vec<String> = SUBCOMMANDS.iter().map(|c| c.to_string()).collect();
+        listed.sort();
+
+        assert_eq!(defined, listed);
+    }
+
+    #[test]
+    fn metric_groups_assign_metadata_to_preceding_metric() {
+        let cli_args = build_cli().get_matches_from(vec!["emit_bosun", "put",
+                                                         "-m", "backup.runtime=42", "-r", "gauge", "-u", "sec", "-d", "Runtime",
+                                                         "-m", "backup.bytes=1024",
+                                                         "-m", "backup.files=12", "-d", "Files", "-u", "files", "-r", "gauge"]);
+        let put_args = cli_args.subcommand_matches("put").unwrap();
+
+        let groups = metric_groups(put_args).unwrap();
+
+        assert_eq!(groups,
+                   vec![MetricGroup {
+                            metric: "backup.runtime".to_string(),
+                            value: "42".to_string(),
+                            rate: Some("gauge".to_string()),
+                            unit: Some("sec".to_string()),
+                            description: Some("Runtime".to_string()),
+                        },
+                        MetricGroup {
+                            metric: "backup.bytes".to_string(),
+                            value: "1024".to_string(),
+                            rate: None,
+                            unit: None,
+                            description: None,
+                        },
+                        MetricGroup {
+                            metric: "backup.files".to_string(),
+                            value: "12".to_string(),
+                            rate: Some("gauge".to_string()),
+                            unit: Some("files".to_string()),
+                            description: Some("Files".to_string()),
+                        }]);
+    }
+
+    #[test]
+    fn metric_groups_fails_on_incomplete_metadata() {
+        let cli_args = build_cli().get_matches_from(vec!["emit_bosun", "put",
+                                                         "-m", "backup.runtime=42", "-r", "gauge", "-u", "sec", "-d", "Runtime",
+                                                         "-m", "backup.bytes=1024", "-u", "bytes"]);
+        let put_args = cli_args.subcommand_matches("put").unwrap();
+
+        let res = metric_groups(put_args);
+
+        assert_eq!(res,
+                   Err("meta data of metric 'backup.bytes' requires rate, unit, and description".to_string()));
+    }
+}
@@ -0,0 +1,166 @@
+//! Long running subcommands, i.e., the agent and the StatsD listener, which run until stopped by a signal.
+
+use std::collections::HashSet;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use bosun_emitter::{BosunClient, now_in_ms};
+use bosun_emitter::agent::Agent;
+use bosun_emitter::spool::Spool;
+use bosun_emitter::statsd;
+
+use super::{emit_batch, emit_with_metadata, flush_spool, send_catalog_metadata};
+use super::config::Config;
+use super::output::{ExitCode, Outcome, msg};
+
+/// Set on SIGTERM or SIGINT to stop the agent or StatsD listener once all data received has been sent.
+static STOP: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn stop(_: libc::c_int) {
+    STOP.store(true, Ordering::SeqCst);
+}
+
+fn stop_on_signals() {
+    let handler = stop as extern "C" fn(libc::c_int);
+    unsafe {
+        libc::signal(libc::SIGTERM, handler as libc::sighandler_t);
+        libc::signal(libc::SIGINT, handler as libc::sighandler_t);
+    }
+}
+
+/// Warns that a TCP port of the agent, unlike its socket, does not restrict who may send data points.
+fn warn_open_port(port: u16) {
+    warn!("Any local user may send data points to TCP port {}, because the socket's mode does not apply to it.",
+          port);
+}
+
+/// Receives data points from local clients and sends them in batches until stopped.
+pub fn run_agent(config: &Config, client: &BosunClient, verbose: bool) -> Outcome {
+    let mut agent = Agent::new();
+    // Without Unix domain sockets, the agent only listens on its TCP ports
+    if cfg!(unix) {
+        if let Err(e) = agent.listen_unix(&config.agent_socket, config.agent_socket_mode) {
+            return Outcome::failure(format!("Failed to listen on '{}', because {}.", config.agent_socket, e),
+                                    ExitCode::Unavailable);
+        }
+        msg(&format!("Listening on '{}'.", config.agent_socket), verbose);
+    } else if config.agent_port.is_none() && config.agent_telnet_port.is_none() && config.agent_graphite_port.is_none() {
+        return Outcome::failure("Cannot listen on a Unix domain socket on this platform; please set a port.",
+                                ExitCode::Usage);
+    }
+    if let Some(port) = config.agent_port {
+        warn_open_port(port);
+        match agent.listen_tcp(("127.0.0.1", port)) {
+            Ok(address) => msg(&format!("Listening on '{}'.", address), verbose),
+            Err(e) => {
+                return Outcome::failure(format!("Failed to listen on port {}, because {}.", port, e),
+                                        ExitCode::Unavailable)
+            }
+        }
+    }
+    if let Some(port) = config.agent_graphite_port {
+        warn_open_port(port);
+        match agent.listen_graphite(("127.0.0.1", port), config.graphite.clone()) {
+            Ok(address) => msg(&format!("Listening for Graphite's plaintext protocol on '{}'.", address), verbose),
+            Err(e) => {
+                return Outcome::failure(format!("Failed to listen on Graphite port {}, because {}.", port, e),
+                                        ExitCode::Unavailable)
+            }
+        }
+    }
+    if let Some(port) = config.agent_telnet_port {
+        warn_open_port(port);
+        match agent.listen_telnet(("127.0.0.1", port)) {
+            Ok(address) => msg(&format!("Listening for OpenTSDB's telnet protocol on '{}'.", address), verbose),
+            Err(e) => {
+                return Outcome::failure(format!("Failed to listen on telnet port {}, because {}.", port, e),
+                                        ExitCode::Unavailable)
+            }
+        }
+    }
+    stop_on_signals();
+
+    let spool = config.spool_dir.as_ref().map(Spool::new);
+    flush_spool(client, spool.as_ref(), verbose);
+    let spool = spool.as_ref();
+    let batch_size = if config.batch_size > 0 { config.batch_size } else { 1 };
+    let flush_interval = Duration::from_secs(config.agent_flush_interval.max(1));
+
+    let mut sent = 0;
+    loop {
+        // Once stopped, only the data points already received are sent
+        let stopping = STOP.load(Ordering::SeqCst);
+        let mut batch = agent.next_batch(batch_size, if stopping { Duration::from_secs(0) } else { flush_interval });
+        if batch.is_empty() {
+            if stopping {
+                break;
+            }
+            continue;
+        }
+        for data_point in &mut batch {
+            config.enrich(data_point);
+        }
+        // The client's meta data cache keeps the catalog's meta data from being sent with each batch
+        let result = send_catalog_metadata(config, client, spool, &batch, &mut HashSet::new())
+            .and_then(|_| emit_batch(client, spool, &batch));
+        match result {
+            Ok(0) => {}
+            Ok(count) => {
+                msg(&format!("Sent {} data points.", count), verbose);
+                sent += count;
+                // Bosun is reachable again, so data points spooled before can be sent, too
+                flush_spool(client, spool, verbose);
+            }
+            Err(err) => error!("Failed to send {} data points, because {:?}.", batch.len(), err),
+        }
+    }
+
+    Outcome::success(format!("Sent {} data points.", sent))
+}
+
+/// Receives StatsD samples and sends their aggregates each flush interval until stopped.
+pub fn run_statsd(config: &Config, client: &BosunClient, verbose: bool) -> Outcome {
+    let listener = match statsd::Listener::bind(config.statsd_address.as_str(), config.statsd_percentiles.clone()) {
+        Ok(listener) => listener,
+        Err(e) => {
+            return Outcome::failure(format!("Failed to listen on '{}', because {}.", config.statsd_address, e),
+                                    ExitCode::Unavailable)
+        }
+    };
+    msg(&format!("Listening on '{}'.", listener.local_addr()), verbose);
+    stop_on_signals();
+
+    let spool = config.spool_dir.as_ref().map(Spool::new);
+    flush_spool(client, spool.as_ref(), verbose);
+    let spool = spool.as_ref();
+    let flush_interval = Duration::from_secs(config.statsd_flush_interval.max(1));
+
+    let mut sent = 0;
+    let mut last_flush = Instant::now();
+    loop {
+        // Sleep in short steps to notice a signal quickly; the samples received so far are sent then
+        let stopping = STOP.load(Ordering::SeqCst);
+        if !stopping && last_flush.elapsed() < flush_interval {
+            thread::sleep(Duration::from_millis(100));
+            continue;
+        }
+        let aggregates = listener.flush(now_in_ms(), last_flush.elapsed());
+        last_flush = Instant::now();
+
+        let count = aggregates.len();
+        let converted = aggregates.into_iter()
+            .map(|a| (a.data_point, a.rate.to_string(), a.unit.to_string(), a.description))
+            .collect();
+        // The client's meta data cache keeps the meta data from being sent each flush interval
+        match emit_with_metadata(config, client, spool, converted, verbose) {
+            Ok(count) => sent += count,
+            Err(err) => error!("Failed to send {} data points, because {:?}.", count, err),
+        }
+        if stopping {
+            break;
+        }
+    }
+
+    Outcome::success(format!("Sent {} data points.", sent))
+}
@@ -0,0 +1,1111 @@
+//! emit_bosun -- Emits a single metric datum and corresponding metric meta data to [Bosun](http:////bosun.org).
+
+#[macro_use]
+extern crate clap;
+extern crate env_logger;
+extern crate libc;
+#[macro_use]
+extern crate log;
+#[cfg(test)]
+extern crate mktemp;
+extern crate reqwest;
+extern crate rustc_serialize;
+extern crate toml;
+
+extern crate bosun_emitter;
+
+mod cli;
+mod config;
+mod daemon;
+mod output;
+
+use clap::{ArgMatches, Shell};
+use env_logger::LogBuilder;
+use log::LogLevelFilter;
+use std::collections::HashSet;
+use std::env;
+use std::fs::File;
+use std::io::{self, BufRead, Read};
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use bosun_emitter::{BosunClient, Collector, Metadata, Datum, DataPoint, Tags, DryRun, EmitterError, DATUM_API_PATH,
+                    METADATA_API_PATH, data_to_json, metadata_to_json, now_in_ms, parse_bool, redact_host};
+#[cfg(unix)]
+use bosun_emitter::agent::AgentClient;
+use bosun_emitter::exec::{self, Execution};
+use bosun_emitter::import::{self, Import, Mapping};
+use bosun_emitter::input::Format;
+use bosun_emitter::prometheus;
+use bosun_emitter::spool::Spool;
+use bosun_emitter::textfile::{self, TextFile};
+
+use cli::{BIN_NAME, MetricGroup, build_cli, completion_metrics, completions, input_format, is_metric_groups, man_page,
+          metric_groups};
+use config::{Config, Env, Source, check_config, format_config, parse_args};
+use daemon::{run_agent, run_statsd};
+use output::{ExitCode, Outcome, Output, exit_with_error, finish, msg};
+
+/// Report the progress of imports every 10 sec
+static IMPORT_PROGRESS_INTERVAL: u64 = 10;
+
+/// Logs to stderr; unless `RUST_LOG` says otherwise, emit_bosun's progress, warnings, and errors are
+/// logged as well as errors of the libraries used.
+fn init_logger() -> Result<(), log::SetLoggerError> {
+    let mut logger = LogBuilder::new();
+    logger.format(|record| format!("{}: {}", record.level(), record.args()));
+    match env::var("RUST_LOG") {
+        Ok(filters) => logger.parse(&filters),
+        Err(_) => {
+            logger.filter(None, LogLevelFilter::Error)
+                .filter(Some(BIN_NAME), LogLevelFilter::Info)
+                .filter(Some("bosun_emitter"), LogLevelFilter::Warn)
+        }
+    };
+
+    logger.init()
+}
+
+fn main() {
+    if init_logger().is_err() {
+        exit_with_error("Could not initiliaze logger", ExitCode::Internal);
+    }
+
+    let cli_args = match build_cli().get_matches_safe() {
+        Ok(cli_args) => cli_args,
+        Err(ref err) if err.kind == clap::ErrorKind::HelpDisplayed || err.kind == clap::ErrorKind::VersionDisplayed => {
+            err.exit()
+        }
+        Err(err) => exit_with_error(&err.message, ExitCode::Usage),
+    };
+
+    match cli_args.subcommand() {
+        ("completions", Some(completions_args)) if completions_args.is_present("shell") => {
+            // unwrap is safe, because clap checks possible values
+            let shell: Shell = completions_args.value_of("shell").unwrap().parse().unwrap();
+            print!("{}", completions(shell));
+            return;
+        }
+        ("man", Some(_)) => {
+            print!("{}", man_page());
+            return;
+        }
+        _ => {}
+    }
+
+    let output = match cli_args.value_of("output") {
+        Some("json") => Output::Json,
+        Some("scollector") => Output::Scollector,
+        _ => Output::Text,
+    };
+    let force: bool = cli_args.is_present("force");
+    let env = Env::system();
+    let verbose: bool = cli_args.is_present("verbose") || env.var("BOSUN_VERBOSE").and_then(parse_bool).unwrap_or(false);
+    let mut config: Config = match parse_args(&cli_args, &env) {
+        Ok(config) => config,
+        Err(err) => {
+            let outcome = Outcome::failure(format!("Failed to parse configuration, because {}.", err), ExitCode::Config);
+            finish(outcome, output, &[]);
+        }
+    };
+    if cli_args.is_present("show-config") {
+        print!("{}", format_config(&config, "toml"));
+    }
+    if let Some(config_args) = cli_args.subcommand_matches("config") {
+        if let Some(show_args) = config_args.subcommand_matches("show") {
+            print!("{}", format_config(&config, show_args.value_of("format").unwrap_or("toml")));
+        }
+        if config_args.subcommand_matches("validate").is_some() {
+            let outcome = match check_config(&config) {
+                Ok(_) => Outcome::success("Configuration is valid."),
+                Err(outcome) => outcome,
+            };
+            finish(outcome, output, &[]);
+        }
+        return;
+    }
+    if cli_args.subcommand_matches("completions").is_some() {
+        for metric in completion_metrics(&config) {
+            println!("{}", metric);
+        }
+        return;
+    }
+    for warning in &config.warnings {
+        warn!("{}", warning);
+    }
+
+    if output == Output::Scollector {
+        // scollector sends what it reads, so nothing is spooled and spooled requests are left for later
+        config.spool_dir = None;
+    }
+
+    let mut client = config.client();
+    client.record_requests = output == Output::Json;
+    if output == Output::Scollector {
+        client.collector = Some(Collector::stdout());
+    }
+    if cli_args.is_present("dry-run") {
+        // Keep stdout for the report in JSON output and for scollector
+        client.dry_run = Some(match output {
+            Output::Text => DryRun::stdout(),
+            Output::Json | Output::Scollector => DryRun::new(io::stderr()),
+        });
+    }
+    let outcome = match cli_args.subcommand() {
+        ("put", Some(put_args)) if put_args.is_present("via-agent") => put_via_agent(&config, &client, put_args, verbose),
+        ("put", Some(put_args)) if put_args.is_present("stdin") => {
+            let format = input_format(put_args);
+            let stdin = io::stdin();
+            match put_lines(&config, &client, stdin.lock(), format, verbose) {
+                Ok(ref malformed) if malformed.is_empty() => Outcome::default(),
+                Ok(malformed) => {
+                    let mut outcome = Outcome::failure(format!("Skipped {} malformed lines.", malformed.len()),
+                                                       ExitCode::MalformedInput);
+                    outcome.details = malformed;
+                    outcome
+                }
+                Err(err) => Outcome::from(err),
+            }
+        }
+        ("put", Some(put_args)) if is_metric_groups(put_args) => {
+            match metric_groups(put_args) {
+                Ok(groups) => {
+                    match metric_group_data_points(&config, &groups) {
+                        Ok(data_points) => Outcome::of(put_metric_groups(&config, &client, &groups, &data_points, verbose)),
+                        Err(err) => Outcome::failure(format!("Cannot send metrics, because {}.", err), ExitCode::MalformedInput),
+                    }
+                }
+                Err(err) => Outcome::failure(format!("Failed to parse metrics, because {}.", err), ExitCode::Usage),
+            }
+        }
+        ("put", Some(_)) => {
+            match (config.metric.is_some(), config.value.is_some(), has_metadata(&config)) {
+                (false, _, _) => Outcome::failure("Cannot send datum without metric name.", ExitCode::Usage),
+                (true, false, _) => Outcome::failure("Cannot send datum without value.", ExitCode::Usage),
+                (true, true, true) => put_datum(&config, &client, Mode::Normal, verbose),
+                (true, true, false) => put_datum(&config, &client, Mode::DatumOnly, verbose),
+            }
+        }
+        ("metadata", Some(metadata_args)) if metadata_args.subcommand_matches("sync").is_some() => {
+            // Syncing is explicit, so meta data sent recently is sent again
+            client.force_metadata = true;
+            match sync_metadata(&config, &client, verbose) {
+                Ok(count) => Outcome::success(format!("Sent meta data of {} metrics.", count)),
+                Err(err) => Outcome::from(err),
+            }
+        }
+        ("metadata", Some(_)) => {
+            if config.metric.is_none() || !has_metadata(&config) {
+                Outcome::failure("Cannot send meta data without metric name, rate, unit, and description.",
+                                 ExitCode::Usage)
+            } else {
+                Outcome::of(run(&config, &client, Mode::MetadataOnly, verbose))
+            }
+        }
+        ("exec", Some(exec_args)) => exec_command(&config, &client, exec_args, verbose),
+        ("agent", Some(_)) => run_agent(&config, &client, verbose),
+        ("statsd", Some(_)) => run_statsd(&config, &client, verbose),
+        ("scrape", Some(scrape_args)) => {
+            // unwrap is safe, because clap requires sources
+            let sources: Vec<&str> = scrape_args.values_of("source").unwrap().collect();
+            scrape(&config, &client, &sources, verbose)
+        }
+        ("textfile", Some(textfile_args)) => {
+            read_textfiles(&config, &client, textfile_args.is_present("file-metrics"), verbose)
+        }
+        ("import", Some(import_args)) => import_file(&config, &client, import_args, verbose),
+        ("check", Some(_)) => {
+            match check_config(&config) {
+                Ok(_) => {
+                    match client.check() {
+                        Ok(_) => Outcome::success(format!("Bosun at '{}' is reachable.", redact_host(&config.host))),
+                        Err(err) => Outcome::from(err),
+                    }
+                }
+                Err(outcome) => outcome,
+            }
+        }
+        _ => {
+            // Earlier versions inferred what to send from the flags set
+            match mode(&config, force) {
+                Ok(mode) => put_datum(&config, &client, mode, verbose),
+                Err(ModeError::NoMetadata) => Outcome::failure("Cannot send datum without meta data.", ExitCode::Usage),
+                Err(ModeError::NoValue) => Outcome::failure("Cannot send datum without value.", ExitCode::Usage),
+                Err(ModeError::NoSuchMode) => {
+                    Outcome::failure(format!("Please use one of the subcommands put, metadata, exec, agent, statsd, scrape, textfile, import, check, or config.\n\n{}",
+                                             cli_args.usage()),
+                                     ExitCode::Usage)
+                }
+            }
+        }
+    };
+
+    finish(outcome, output, &client.sent_requests());
+}
+
+/// Runs a command and sends its metrics; exits with the command's exit code.
+fn exec_command(config: &Config, client: &BosunClient, exec_args: &ArgMatches, verbose: bool) -> Outcome {
+    let metric = match config.metric {
+        Some(ref metric) => metric,
+        None => return Outcome::failure("Cannot run command without metric name.", ExitCode::Usage),
+    };
+    // unwrap is safe, because clap requires a command
+    let command: Vec<String> = exec_args.values_of("command").unwrap().map(|c| c.to_string()).collect();
+    let execution = match exec::run(&command[0], &command[1..]) {
+        Ok(execution) => execution,
+        Err(e) => {
+            let exit_code = if e.kind() == io::ErrorKind::NotFound {
+                ExitCode::CommandNotFound
+            } else {
+                ExitCode::CannotExecute
+            };
+            return Outcome::failure(format!("Failed to run '{}', because {}.", command[0], e), exit_code);
+        }
+    };
+    msg(&format!("'{}' exited with {} after {:?}.", command[0], execution.exit_code, execution.runtime), verbose);
+
+    let mut outcome = match emit_execution(config, client, metric, &command[0], &execution, exec_args.is_present("rusage"), verbose) {
+        Ok(_) => Outcome::default(),
+        Err(err) => Outcome::failure(format!("Failed to send metrics for '{}', because {:?}.", command[0], err),
+                                     ExitCode::from(&err)),
+    };
+    // A failed command takes precedence, so its exit code is never hidden by a failure to send
+    if execution.exit_code != 0 || outcome.error.is_none() {
+        outcome.exit_code = execution.exit_code;
+    }
+
+    outcome
+}
+
+/// Sends data points converted from other formats with their meta data `(rate, unit, description)`
+/// in batches; meta data is sent once per metric. Returns the number of data points sent.
+pub fn emit_with_metadata(config: &Config,
+                      client: &BosunClient,
+                      spool: Option<&Spool>,
+                      converted: Vec<(DataPoint, String, String, String)>,
+                      verbose: bool)
+                      -> Result<usize, EmitterError> {
+    let mut data_points = Vec::with_capacity(converted.len());
+    let mut metadata: Vec<(String, String, String, String)> = Vec::new();
+    for (mut data_point, rate, unit, description) in converted {
+        config.enrich(&mut data_point);
+        if !metadata.iter().any(|m| m.0 == data_point.metric) {
+            metadata.push((data_point.metric.clone(), rate, unit, description));
+        }
+        data_points.push(data_point);
+    }
+    if !metadata.is_empty() {
+        let metadata: Vec<Metadata> = metadata.iter()
+            .map(|&(ref metric, ref rate, ref unit, ref description)| Metadata::new(metric, rate, unit, description))
+            .collect();
+        try!(send_metadata(&metadata, client, spool));
+    }
+    let batch_size = if config.batch_size > 0 { config.batch_size } else { 1 };
+    let mut sent = 0;
+    for batch in data_points.chunks(batch_size) {
+        let count = try!(emit_batch(client, spool, batch));
+        if count > 0 {
+            msg(&format!("Sent {} data points.", count), verbose);
+        }
+        sent += count;
+    }
+
+    Ok(sent)
+}
+
+/// Scrapes Prometheus endpoints or files and sends their samples; sources that cannot be read are
+/// skipped and reported.
+fn scrape(config: &Config, client: &BosunClient, sources: &[&str], verbose: bool) -> Outcome {
+    let spool = config.spool_dir.as_ref().map(Spool::new);
+    flush_spool(client, spool.as_ref(), verbose);
+    let spool = spool.as_ref();
+
+    let mut sent = 0;
+    let mut unavailable = Vec::new();
+    let mut malformed = Vec::new();
+    for source in sources {
+        let timestamp = now_in_ms();
+        let text = if source.starts_with("http://") || source.starts_with("https://") {
+            prometheus::fetch(source, config.timeout).map_err(|err| format!("{:?}", err))
+        } else if *source == "-" {
+            let mut text = String::new();
+            io::stdin().read_to_string(&mut text).map(|_| text).map_err(|e| e.to_string())
+        } else {
+            let mut text = String::new();
+            File::open(source).and_then(|mut f| f.read_to_string(&mut text)).map(|_| text).map_err(|e| e.to_string())
+        };
+        let text = match text {
+            Ok(text) => text,
+            Err(e) => {
+                unavailable.push(format!("Failed to scrape '{}', because {}.", source, e));
+                continue;
+            }
+        };
+        let (samples, errors) = prometheus::parse(&text, timestamp, &config.label_rules);
+        malformed.extend(errors.into_iter().map(|e| format!("{}: {}", source, e)));
+        msg(&format!("Scraped {} samples from '{}'.", samples.len(), source), verbose);
+        let converted = samples.into_iter()
+            .map(|s| (s.data_point, s.rate.to_string(), s.unit, s.description))
+            .collect();
+        match emit_with_metadata(config, client, spool, converted, verbose) {
+            Ok(count) => sent += count,
+            Err(err) => return Outcome::from(err),
+        }
+    }
+
+    let mut outcome = if !unavailable.is_empty() {
+        Outcome::failure(format!("Failed to scrape {} of {} sources.", unavailable.len(), sources.len()),
+                         ExitCode::Unavailable)
+    } else if !malformed.is_empty() {
+        Outcome::failure(format!("Skipped {} malformed lines.", malformed.len()), ExitCode::MalformedInput)
+    } else {
+        return Outcome::success(format!("Sent {} data points.", sent));
+    };
+    outcome.details = unavailable;
+    outcome.details.extend(malformed);
+
+    outcome
+}
+
+/// Reads all files of the textfile directory and sends their data points; files that cannot be
+/// read are skipped and reported.
+fn read_textfiles(config: &Config, client: &BosunClient, file_metrics: bool, verbose: bool) -> Outcome {
+    let paths = match textfile::files(&config.textfile_dir) {
+        Ok(paths) => paths,
+        Err(e) => {
+            return Outcome::failure(format!("Failed to read '{}', because {}.", config.textfile_dir, e),
+                                    ExitCode::Unavailable)
+        }
+    };
+    let spool = config.spool_dir.as_ref().map(Spool::new);
+    flush_spool(client, spool.as_ref(), verbose);
+    let spool = spool.as_ref();
+
+    let now = now_in_ms();
+    let mut converted = Vec::new();
+    let mut data_points = Vec::new();
+    let mut unavailable = Vec::new();
+    let mut malformed = Vec::new();
+    for path in &paths {
+        let file = match TextFile::read(path, &config.label_rules) {
+            Ok(file) => file,
+            Err(e) => {
+                unavailable.push(format!("Failed to read '{}', because {}.", path.display(), e));
+                continue;
+            }
+        };
+        msg(&format!("Read {} data points from '{}'.", file.samples.len() + file.data_points.len(), path.display()),
+            verbose);
+        if file_metrics {
+            converted.extend(file.metrics(now).into_iter().map(|s| (s.data_point, s.rate.to_string(), s.unit, s.description)));
+        }
+        malformed.extend(file.errors.iter().map(|e| format!("{}: {}", path.display(), e)));
+        converted.extend(file.samples.into_iter().map(|s| (s.data_point, s.rate.to_string(), s.unit, s.description)));
+        data_points.extend(file.data_points);
+    }
+
+    let mut sent = match emit_with_metadata(config, client, spool, converted, verbose) {
+        Ok(count) => count,
+        Err(err) => return Outcome::from(err),
+    };
+    // Data points of line formats get the catalog's meta data like `put --stdin`
+    for data_point in &mut data_points {
+        config.enrich(data_point);
+    }
+    let batch_size = if config.batch_size > 0 { config.batch_size } else { 1 };
+    let mut metadata_sent = HashSet::new();
+    for batch in data_points.chunks(batch_size) {
+        let result = send_catalog_metadata(config, client, spool, batch, &mut metadata_sent)
+            .and_then(|_| emit_batch(client, spool, batch));
+        match result {
+            Ok(count) => {
+                msg(&format!("Sent {} data points.", count), verbose);
+                sent += count;
+            }
+            Err(err) => return Outcome::from(err),
+        }
+    }
+
+    let mut outcome = if !unavailable.is_empty() {
+        Outcome::failure(format!("Failed to read {} of {} files.", unavailable.len(), paths.len()),
+                         ExitCode::Unavailable)
+    } else if !malformed.is_empty() {
+        Outcome::failure(format!("Skipped {} malformed lines.", malformed.len()), ExitCode::MalformedInput)
+    } else {
+        return Outcome::success(format!("Sent {} data points from {} files.", sent, paths.len()));
+    };
+    outcome.details = unavailable;
+    outcome.details.extend(malformed);
+
+    outcome
+}
+
+/// Imports data points from a CSV or JSON file in batches; stops at the first batch that fails and
+/// reports the line to resume from. Malformed records are skipped and reported.
+fn import_file(config: &Config, client: &BosunClient, import_args: &ArgMatches, verbose: bool) -> Outcome {
+    let (format, mapping) = match import_mapping(import_args) {
+        Ok(mapping) => mapping,
+        Err(err) => return Outcome::failure(format!("Failed to map columns, because {}.", err), ExitCode::Usage),
+    };
+    // unwraps are safe, because clap requires the file and validates the numbers
+    let file = import_args.value_of("file").unwrap();
+    let rate_limit = import_args.value_of("rate-limit").map(|r| r.parse::<usize>().unwrap()).unwrap_or(0);
+    let resume_from = import_args.value_of("resume-from").map(|l| l.parse::<usize>().unwrap()).unwrap_or(0);
+    let reader: Box<BufRead> = if file == "-" {
+        Box::new(io::BufReader::new(io::stdin()))
+    } else {
+        match File::open(file) {
+            Ok(f) => Box::new(io::BufReader::new(f)),
+            Err(e) => return Outcome::failure(format!("Failed to read '{}', because {}.", file, e), ExitCode::Unavailable),
+        }
+    };
+
+    let mut batch_size = if config.batch_size > 0 { config.batch_size } else { 1 };
+    if rate_limit > 0 {
+        batch_size = batch_size.min(rate_limit);
+    }
+    let progress_interval = Duration::from_secs(IMPORT_PROGRESS_INTERVAL);
+    let started = Instant::now();
+    let mut last_progress = Instant::now();
+    let mut sent = 0;
+    let mut batch = Vec::with_capacity(batch_size);
+    // First line of the batch, or the line after the last record if the batch is empty
+    let mut resume_line = resume_from.max(1);
+    let mut metadata_sent = HashSet::new();
+    let mut malformed = Vec::new();
+    let mut records = Import::new(reader, format, mapping);
+    loop {
+        let record = records.next();
+        let done = record.is_none();
+        match record {
+            None => {}
+            Some(Ok((line, _))) if line < resume_from => continue,
+            Some(Ok((line, Ok(mut data_point)))) => {
+                config.enrich(&mut data_point);
+                if batch.is_empty() {
+                    resume_line = line;
+                }
+                batch.push(data_point);
+            }
+            Some(Ok((line, Err(e)))) => {
+                malformed.push(format!("line {}: {}", line, e));
+                if batch.is_empty() {
+                    resume_line = line + 1;
+                }
+            }
+            Some(Err(ref e)) if e.kind() == io::ErrorKind::InvalidInput => {
+                return Outcome::failure(format!("Failed to import '{}', because {}.", file, e), ExitCode::Usage)
+            }
+            Some(Err(e)) => {
+                let mut outcome = Outcome::failure(format!("Failed to read '{}', because {}.", file, e),
+                                                   ExitCode::Unavailable);
+                outcome.details.push(resume_hint(sent, resume_line));
+                return outcome;
+            }
+        }
+        if batch.len() >= batch_size || (done && !batch.is_empty()) {
+            // Wait until the data points sent so far are due at the rate limit, if any
+            if let Some(due) = (sent * 1000).checked_div(rate_limit) {
+                let due = Duration::from_millis(due as u64);
+                let elapsed = started.elapsed();
+                if due > elapsed {
+                    thread::sleep(due - elapsed);
+                }
+            }
+            let result = send_catalog_metadata(config, client, None, &batch, &mut metadata_sent)
+                .and_then(|_| emit_batch(client, None, &batch));
+            match result {
+                Ok(count) => {
+                    msg(&format!("Sent {} data points.", count), verbose);
+                    sent += count;
+                }
+                Err(err) => {
+                    let mut outcome = Outcome::from(err);
+                    outcome.details.push(resume_hint(sent, resume_line));
+                    return outcome;
+                }
+            }
+            batch.clear();
+            if last_progress.elapsed() >= progress_interval {
+                info!("Imported {} data points before line {}.", sent, records.line() + 1);
+                last_progress = Instant::now();
+            }
+            resume_line = records.line() + 1;
+        }
+        if done {
+            break;
+        }
+    }
+
+    if malformed.is_empty() {
+        return Outcome::success(format!("Imported {} data points.", sent));
+    }
+    let mut outcome = Outcome::failure(format!("Imported {} data points and skipped {} malformed records.",
+                                               sent,
+                                               malformed.len()),
+                                       ExitCode::MalformedInput);
+    outcome.details = malformed;
+
+    outcome
+}
+
+/// Returns the input format and column mapping of `import`.
+fn import_mapping(import_args: &ArgMatches) -> Result<(import::Format, Mapping), String> {
+    // unwrap is safe, because clap checks possible values and sets a default
+    let format = match import_args.value_of("format").unwrap().parse().unwrap() {
+        import::Format::Csv(_) => {
+            let delimiter = match import_args.value_of("delimiter").unwrap_or(",") {
+                "\\t" => '\t',
+                delimiter if delimiter.chars().count() == 1 => delimiter.chars().next().unwrap(),
+                delimiter => return Err(format!("delimiter '{}' is not a single character", delimiter)),
+            };
+            import::Format::Csv(delimiter)
+        }
+        format => format,
+    };
+    let column = |name, default| import::Column::parse(import_args.value_of(name).unwrap_or(default));
+    let mut tags = Vec::new();
+    for tag in import_args.values_of("tag-column").into_iter().flatten() {
+        tags.push(try!(Mapping::parse_tag(tag)));
+    }
+    let mapping = Mapping {
+        metric: column("metric-column", "metric"),
+        metric_name: import_args.value_of("metric").map(|m| m.to_string()),
+        timestamp: column("timestamp-column", "timestamp"),
+        timestamp_format: import_args.value_of("timestamp-format").map(|f| f.to_string()),
+        value: column("value-column", "value"),
+        tags: tags,
+    };
+
+    Ok((format, mapping))
+}
+
+/// Tells where to resume a failed import, i.e., the first line not sent.
+fn resume_hint(sent: usize, line: usize) -> String {
+    format!("Imported {} data points; resume with --resume-from {}.", sent, line)
+}
+
+/// Passes data points to the local agent instead of sending them to Bosun.
+///
+/// The agent adds the metric prefix, so data points get only the configured tags. Meta data set
+/// explicitly is sent directly, because the agent only sends meta data declared in its catalog.
+#[cfg(unix)]
+fn put_via_agent(config: &Config, client: &BosunClient, put_args: &ArgMatches, verbose: bool) -> Outcome {
+    if client.dry_run.is_some() {
+        return Outcome::failure("Cannot pass data points to the agent in a dry run; run the agent with --dry-run instead.",
+                                ExitCode::Usage);
+    }
+    let timestamp = config.timestamp.unwrap_or_else(now_in_ms);
+    let mut data_points = Vec::new();
+    let mut metadata: Vec<(String, String, String, String)> = Vec::new();
+    if put_args.is_present("stdin") {
+        // Read below while sending, so stdin is not read into memory at once
+    } else if is_metric_groups(put_args) {
+        let groups = match metric_groups(put_args) {
+            Ok(groups) => groups,
+            Err(err) => return Outcome::failure(format!("Failed to parse metrics, because {}.", err), ExitCode::Usage),
+        };
+        for group in groups {
+            if let (Some(rate), Some(unit), Some(description)) = (group.rate, group.unit, group.description) {
+                metadata.push((config.prefixed(&group.metric), rate, unit, description));
+            }
+            data_points.push(DataPoint::new(group.metric, timestamp, group.value, Tags::new()));
+        }
+    } else {
+        match (&config.metric, &config.value) {
+            (&None, _) => return Outcome::failure("Cannot send datum without metric name.", ExitCode::Usage),
+            (_, &None) => return Outcome::failure("Cannot send datum without value.", ExitCode::Usage),
+            (&Some(ref metric), &Some(ref value)) => {
+                data_points.push(DataPoint::new(metric.as_str(), timestamp, value.as_str(), Tags::new()))
+            }
+        }
+        let is_from_catalog = match *config.source("rate") {
+            Source::Catalog(_) => true,
+            _ => false,
+        };
+        if let (Some(metric), Some(rate), Some(unit), Some(description), false) =
+            (config.metric_name(), config.rate.clone(), config.unit.clone(), config.description.clone(), is_from_catalog) {
+            metadata.push((metric, rate, unit, description));
+        }
+    }
+
+    let mut agent = match AgentClient::connect(&config.agent_socket) {
+        Ok(agent) => agent,
+        Err(err) => return Outcome::from(err),
+    };
+    let mut malformed = Vec::new();
+    let sent = if put_args.is_present("stdin") {
+        let format = input_format(put_args);
+        let stdin = io::stdin();
+        let mut data = Vec::new();
+        for (index, line) in stdin.lock().lines().enumerate() {
+            match line.map(|line| config.parse_line(format, &line)) {
+                Ok(Ok(parsed)) => data.extend(parsed),
+                Ok(Err(e)) => malformed.push(format!("line {}: {}", index + 1, e)),
+                Err(e) => return Outcome::from(EmitterError::from(e)),
+            }
+            if let Err(err) = send_to_agent(config, &mut agent, &mut data) {
+                return Outcome::from(err);
+            }
+        }
+        Ok(())
+    } else {
+        send_to_agent(config, &mut agent, &mut data_points)
+    };
+    if let Err(err) = sent {
+        return Outcome::from(err);
+    }
+    let reply = match agent.finish() {
+        Ok(reply) => reply,
+        Err(err) => return Outcome::from(err),
+    };
+    msg(&format!("Passed {} data points to the agent.", reply.accepted), verbose);
+    malformed.extend(reply.errors);
+
+    if !metadata.is_empty() {
+        let metadata: Vec<Metadata> = metadata.iter()
+            .map(|&(ref metric, ref rate, ref unit, ref description)| Metadata::new(metric, rate, unit, description))
+            .collect();
+        let spool = config.spool_dir.as_ref().map(Spool::new);
+        msg("Sending meta data.", verbose);
+        if let Err(err) = send_metadata(&metadata, client, spool.as_ref()) {
+            return Outcome::from(err);
+        }
+    }
+
+    if malformed.is_empty() {
+        Outcome::default()
+    } else {
+        let mut outcome = Outcome::failure(format!("Skipped {} malformed lines.", malformed.len()), ExitCode::MalformedInput);
+        outcome.details = malformed;
+        outcome
+    }
+}
+
+/// Fails, because clients reach the agent via its Unix domain socket only.
+#[cfg(not(unix))]
+fn put_via_agent(_: &Config, _: &BosunClient, _: &ArgMatches, _: bool) -> Outcome {
+    Outcome::failure("Cannot pass data points to the agent, because Unix domain sockets are not supported on this platform.",
+                     ExitCode::Usage)
+}
+
+/// Adds the configured tags to data points and passes them to the agent.
+#[cfg(unix)]
+fn send_to_agent(config: &Config, agent: &mut AgentClient, data_points: &mut Vec<DataPoint>) -> bosun_emitter::EmitterResult {
+    for mut data_point in data_points.drain(..) {
+        config.add_tags(&mut data_point);
+        try!(agent.send(&data_point));
+    }
+
+    Ok(())
+}
+
+/// Sends the meta data of all metrics declared in the catalog in one request.
+fn sync_metadata(config: &Config, client: &BosunClient, verbose: bool) -> Result<usize, EmitterError> {
+    for entry in config.catalog.entries().iter().filter(|e| e.is_pattern()) {
+        msg(&format!("Skipping pattern '{}'; meta data can only be sent for metric names.", entry.name),
+            verbose);
+    }
+    let metadata = config.catalog.metadata();
+    if metadata.is_empty() {
+        return Ok(0);
+    }
+
+    let spool = config.spool_dir.as_ref().map(Spool::new);
+    try!(send_metadata(&metadata, client, spool.as_ref()));
+
+    Ok(metadata.len())
+}
+
+/// Converts metric groups to data points with the metric prefix and the configured tags; fails if one
+/// of them is invalid.
+fn metric_group_data_points(config: &Config, groups: &[MetricGroup]) -> Result<Vec<DataPoint>, String> {
+    let timestamp = config.timestamp.unwrap_or_else(now_in_ms);
+    let mut data_points = Vec::new();
+    for group in groups {
+        let mut data_point = DataPoint::new(group.metric.as_str(), timestamp, group.value.as_str(), Tags::new());
+        config.enrich(&mut data_point);
+        try!(data_point.validate().map_err(|e| format!("{} of metric '{}'", e, group.metric)));
+        data_points.push(data_point);
+    }
+
+    Ok(data_points)
+}
+
+/// Sends the data points of all metrics as one batch and their meta data in one request.
+fn put_metric_groups(config: &Config,
+                     client: &BosunClient,
+                     groups: &[MetricGroup],
+                     data_points: &[DataPoint],
+                     verbose: bool)
+                     -> bosun_emitter::EmitterResult {
+    let spool = config.spool_dir.as_ref().map(Spool::new);
+    flush_spool(client, spool.as_ref(), verbose);
+    let spool = spool.as_ref();
+
+    let metadata: Vec<Metadata> = groups.iter()
+        .zip(data_points.iter())
+        .filter_map(|(group, data_point)| match (&group.rate, &group.unit, &group.description) {
+            (&Some(ref rate), &Some(ref unit), &Some(ref description)) => {
+                Some(Metadata::new(&data_point.metric, rate, unit, description))
+            }
+            _ => config.catalog.lookup(&data_point.metric),
+        })
+        .collect();
+    if !metadata.is_empty() {
+        msg("Sending meta data.", verbose);
+        try!(send_metadata(&metadata, client, spool));
+    }
+
+    msg("Sending data.", verbose);
+    try!(emit_batch(client, spool, data_points));
+
+    Ok(())
+}
+
+fn has_metadata(config: &Config) -> bool {
+    config.rate.is_some() && config.unit.is_some() && config.description.is_some()
+}
+
+enum Mode {
+    Normal,
+    MetadataOnly,
+    DatumOnly,
+}
+
+enum ModeError {
+    NoMetadata,
+    NoValue,
+    NoSuchMode,
+}
+
+/// Sends the datum and meta data as the mode says; a datum with an invalid metric name, value, or tag
+/// is not sent.
+fn put_datum(config: &Config, client: &BosunClient, mode: Mode, verbose: bool) -> Outcome {
+    match mode {
+        Mode::MetadataOnly => {}
+        Mode::Normal | Mode::DatumOnly => {
+            // unwraps are safe, because mode analysis already checked these values are set
+            let datum = DataPoint::new(config.metric_name().unwrap(),
+                                       config.timestamp.unwrap_or_else(now_in_ms),
+                                       config.value.as_ref().unwrap().as_str(),
+                                       config.tags.clone());
+            if let Err(err) = datum.validate() {
+                return Outcome::failure(format!("Cannot send datum, because {}.", err), ExitCode::MalformedInput);
+            }
+        }
+    }
+
+    Outcome::of(run(config, client, mode, verbose))
+}
+
+fn run(config: &Config, client: &BosunClient, mode: Mode, verbose: bool) -> bosun_emitter::EmitterResult {
+    let spool = config.spool_dir.as_ref().map(Spool::new);
+    flush_spool(client, spool.as_ref(), verbose);
+    let spool = spool.as_ref();
+
+    match mode {
+        Mode::Normal => {
+            msg("Sending meta data.", verbose);
+            try!(emit_metadata(config, client, spool));
+
+            msg("Sending datum.", verbose);
+            emit_datum(config, client, spool)
+        }
+        Mode::MetadataOnly => {
+           msg("Sending meta data.", verbose);
+            emit_metadata(config, client, spool)
+        }
+        Mode::DatumOnly => {
+            msg("Sending datum.", verbose);
+            emit_datum(config, client, spool)
+        }
+    }
+}
+
+pub fn flush_spool(client: &BosunClient, spool: Option<&Spool>, verbose: bool) {
+    if let Some(spool) = spool {
+        match spool.flush(client) {
+            Ok(0) => {}
+            Ok(sent) => msg(&format!("Sent {} spooled requests.", sent), verbose),
+            Err(err) => msg(&format!("Failed to send spooled requests, because {:?}.", err), verbose),
+        }
+    }
+}
+
+/// We support two modes officially and more mode unofficially.
+/// 1. Send Datum with Metadata
+/// 1. Send only Metadata
+/// 1. Send Datum without Metadata -- only with `--force`
+fn mode(config: &Config, force: bool) -> Result<Mode, ModeError> {
+    let mode_config = (config.metric.is_some(),
+                       config.value.is_some(),
+                       config.rate.is_some(),
+                       config.unit.is_some(),
+                       config.description.is_some(),
+                       force);
+    match mode_config {
+        (true, true, true, true, true, _) => Ok(Mode::Normal),
+        (true, _, true, true, true, _) => Ok(Mode::MetadataOnly),
+        (true, true, _, _, _, true) => Ok(Mode::DatumOnly),
+        (true, true, _, _, _, false) => Err(ModeError::NoMetadata),
+        (true, false, _, _, _, _) => Err(ModeError::NoValue),
+        _ => Err(ModeError::NoSuchMode),
+    }
+}
+
+fn emit_datum(config: &Config, client: &BosunClient, spool: Option<&Spool>) -> bosun_emitter::EmitterResult {
+    // unwraps are safe, because mode analysis already checked these values are set
+    let metric = config.metric_name().unwrap();
+    let datum = Datum::new(&metric,
+                           config.timestamp.unwrap_or_else(now_in_ms),
+                           config.value.as_ref().unwrap(),
+                           &config.tags);
+    match (client.emit_datum(&datum), spool) {
+        (Err(EmitterError::EmitError(e)), Some(spool)) => {
+            let file = try!(spool.store_datum(&datum));
+            warn!("Failed to send datum, because {}; spooled to '{}'.", e, file.display());
+            Ok(())
+        }
+        (res, _) => res,
+    }
+}
+
+fn emit_metadata(config: &Config, client: &BosunClient, spool: Option<&Spool>) -> bosun_emitter::EmitterResult {
+    // unwraps are safe, because mode analysis already checked these values are set
+    let metric = config.metric_name().unwrap();
+    let metadata = Metadata::new(&metric,
+                                 config.rate.as_ref().unwrap(),
+                                 config.unit.as_ref().unwrap(),
+                                 config.description.as_ref().unwrap());
+    send_metadata(&[metadata], client, spool)
+}
+
+/// Sends meta data in one request, and spools it if it cannot be sent.
+fn send_metadata(metadata: &[Metadata], client: &BosunClient, spool: Option<&Spool>) -> bosun_emitter::EmitterResult {
+    match (client.emit_metadata_list(metadata), spool) {
+        (Err(EmitterError::EmitError(e)), Some(spool)) => {
+            let file = try!(spool.store(METADATA_API_PATH, &try!(metadata_to_json(metadata))));
+            warn!("Failed to send meta data, because {}; spooled to '{}'.", e, file.display());
+            Ok(())
+        }
+        (res, _) => res,
+    }
+}
+
+/// Sends the metrics of a command's execution together with their meta data.
+fn emit_execution(config: &Config,
+                  client: &BosunClient,
+                  metric: &str,
+                  command: &str,
+                  execution: &Execution,
+                  rusage: bool,
+                  verbose: bool)
+                  -> bosun_emitter::EmitterResult {
+    let spool = config.spool_dir.as_ref().map(Spool::new);
+    flush_spool(client, spool.as_ref(), verbose);
+    let spool = spool.as_ref();
+
+    let mut data_points = execution.data_points(metric, now_in_ms(), rusage);
+    for data_point in &mut data_points {
+        config.enrich(data_point);
+    }
+
+    msg("Sending meta data.", verbose);
+    let command_name = Path::new(command).file_name().and_then(|n| n.to_str()).unwrap_or(command);
+    let descriptions: Vec<(&str, &str, String)> = data_points.iter()
+        .filter_map(|data_point| {
+            let suffix = data_point.metric.rsplit('.').next().unwrap_or("");
+            exec::METRICS.iter().find(|m| m.0 == suffix).map(|&(_, unit, description)| {
+                (data_point.metric.as_ref(), unit, format!("{} of '{}'", description, command_name))
+            })
+        })
+        .collect();
+    let metadata: Vec<Metadata> = descriptions.iter()
+        .map(|&(metric, unit, ref description)| Metadata::new(metric, "gauge", unit, description))
+        .collect();
+    try!(send_metadata(&metadata, client, spool));
+
+    msg("Sending data.", verbose);
+    try!(emit_batch(client, spool, &data_points));
+
+    Ok(())
+}
+
+/// Reads data points line by line, enriches them by the configuration, and sends them in batches.
+///
+/// Malformed lines are skipped and returned with their line numbers.
+fn put_lines<R: BufRead>(config: &Config,
+                         client: &BosunClient,
+                         reader: R,
+                         format: Format,
+                         verbose: bool)
+                         -> Result<Vec<String>, EmitterError> {
+    let spool = config.spool_dir.as_ref().map(Spool::new);
+    let batch_size = if config.batch_size > 0 { config.batch_size } else { 1 };
+
+    let mut batch: Vec<DataPoint> = Vec::with_capacity(batch_size);
+    let mut malformed = Vec::new();
+    let mut sent = 0;
+    let mut metadata_sent = HashSet::new();
+    for (index, line) in reader.lines().enumerate() {
+        let line = try!(line);
+        match config.parse_line(format, &line) {
+            Ok(data) => {
+                for mut data_point in data {
+                    config.enrich(&mut data_point);
+                    // The configured tags and the metric prefix may make a valid line invalid
+                    match data_point.validate() {
+                        Ok(_) => batch.push(data_point),
+                        Err(e) => malformed.push(format!("line {}: {}", index + 1, e)),
+                    }
+                }
+            }
+            Err(e) => malformed.push(format!("line {}: {}", index + 1, e)),
+        }
+        if batch.len() >= batch_size {
+            try!(send_catalog_metadata(config, client, spool.as_ref(), &batch, &mut metadata_sent));
+            sent += try!(emit_batch(client, spool.as_ref(), &batch));
+            batch.clear();
+        }
+    }
+    if !batch.is_empty() {
+        try!(send_catalog_metadata(config, client, spool.as_ref(), &batch, &mut metadata_sent));
+        sent += try!(emit_batch(client, spool.as_ref(), &batch));
+    }
+    msg(&format!("Sent {} data points.", sent), verbose);
+
+    Ok(malformed)
+}
+
+/// Sends the catalog's meta data of all metrics in a batch whose meta data has not been sent yet.
+pub fn send_catalog_metadata(config: &Config,
+                         client: &BosunClient,
+                         spool: Option<&Spool>,
+                         batch: &[DataPoint],
+                         metadata_sent: &mut HashSet<String>)
+                         -> bosun_emitter::EmitterResult {
+    let mut metadata = Vec::new();
+    for data_point in batch {
+        if metadata_sent.insert(data_point.metric.clone()) {
+            if let Some(m) = config.catalog.lookup(&data_point.metric) {
+                metadata.push(m);
+            }
+        }
+    }
+    if metadata.is_empty() {
+        return Ok(());
+    }
+
+    send_metadata(&metadata, client, spool)
+}
+
+pub fn emit_batch(client: &BosunClient, spool: Option<&Spool>, batch: &[DataPoint]) -> Result<usize, EmitterError> {
+    let data: Vec<Datum> = batch.iter().map(|data_point| data_point.as_datum()).collect();
+    match (client.emit_data(&data), spool) {
+        (Ok(_), _) => Ok(data.len()),
+        (Err(EmitterError::EmitError(e)), Some(spool)) => {
+            let file = try!(spool.store(DATUM_API_PATH, &try!(data_to_json(&data))));
+            warn!("Failed to send {} data points, because {}; spooled to '{}'.", data.len(), e, file.display());
+            Ok(0)
+        }
+        (Err(err), _) => Err(err),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Mode, exec_command, import_mapping, metric_group_data_points, mode, put_datum, put_lines};
+    use cli::{build_cli, metric_groups};
+    use config::{Config, parse_args};
+    use config::tests::test_env;
+    use output::ExitCode;
+    use bosun_emitter::import::{self, Column};
+    use bosun_emitter::input::Format;
+
+    #[test]
+    fn import_options_map_columns() {
+        let cli_args = build_cli().get_matches_from(vec!["emit_bosun", "import", "--delimiter", "\\t", "--metric", "backup.size",
+                                                         "--value-column", "3", "--timestamp-format", "%Y-%m-%d",
+                                                         "--tag-column", "job=type", "--tag-column", "host", "backups.tsv"]);
+        let (format, mapping) = import_mapping(cli_args.subcommand_matches("import").unwrap()).unwrap();
+
+        assert_eq!(format, import::Format::Csv('\t'));
+        assert_eq!(mapping.metric_name, Some("backup.size".to_string()));
+        assert_eq!(mapping.timestamp, Column::Name("timestamp".to_string()));
+        assert_eq!(mapping.value, Column::Index(3));
+        assert_eq!(mapping.timestamp_format, Some("%Y-%m-%d".to_string()));
+        assert_eq!(mapping.tags,
+                   vec![(Column::Name("job".to_string()), "type".to_string()),
+                        (Column::Name("host".to_string()), "host".to_string())]);
+
+        let cli_args = build_cli().get_matches_from(vec!["emit_bosun", "import", "--format", "json", "--tag-column", "2", "-"]);
+        assert!(import_mapping(cli_args.subcommand_matches("import").unwrap()).is_err());
+        assert!(build_cli().get_matches_from_safe(vec!["emit_bosun", "import", "--metric", "a", "--metric-column", "b", "-"]).is_err());
+    }
+
+    #[test]
+    fn put_lines_reports_malformed_lines() {
+        let mut config = Config::default();
+        // Nothing is listening here; but there is nothing to send either
+        config.host = "localhost:1".to_string();
+        let input = "\n# comment\nbackup.runtime 1458066838 abc\nbackup.runtime 1458066838\n";
+
+        let malformed = put_lines(&config, &config.client(), input.as_bytes(), Format::OpenTsdb, false).unwrap();
+
+        assert_eq!(malformed, vec!["line 3: invalid value 'abc'", "line 4: missing value"]);
+    }
+
+    #[test]
+    fn put_rejects_tags_bosun_does_not_accept() {
+        let cli_args = build_cli().get_matches_from(vec!["emit_bosun", "put", "--host", "localhost:1", "--metric",
+                                                         "lukas.tests.count", "--value", "1", "--tag", "dc=\"eu west\""]);
+        let config = parse_args(&cli_args, &test_env()).unwrap();
+        let client = config.client();
+
+        let outcome = put_datum(&config, &client, Mode::DatumOnly, false);
+        assert_eq!(outcome.exit_code, ExitCode::MalformedInput as i32);
+        assert_eq!(outcome.error, Some("Cannot send datum, because invalid tag 'dc=eu west'.".to_string()));
+
+        let cli_args = build_cli().get_matches_from(vec!["emit_bosun", "put", "--metric", "lukas.tests.count=1"]);
+        let groups = metric_groups(cli_args.subcommand_matches("put").unwrap()).unwrap();
+        assert_eq!(metric_group_data_points(&config, &groups).unwrap_err(),
+                   "invalid tag 'dc=eu west' of metric 'lukas.tests.count'");
+
+        let malformed = put_lines(&config, &client, "lukas.tests.count 1458066838 1\n".as_bytes(), Format::OpenTsdb, false)
+            .unwrap();
+        assert_eq!(malformed, vec!["line 1: invalid tag 'dc=eu west'"]);
+    }
+
+    #[test]
+    fn flags_without_subcommand_still_work() {
+        let cli_args = build_cli().get_matches_from(vec!["emit_bosun", "--host", "bosun:8070", "-m", "backup.runtime",
+                                                         "-v", "42", "-r", "gauge", "-u", "sec", "-d", "Runtime"]);
+
+        let config = parse_args(&cli_args, &test_env()).unwrap();
+
+        assert_eq!(cli_args.subcommand_name(), None);
+        match mode(&config, false) {
+            Ok(Mode::Normal) => {}
+            _ => panic!("expected to send datum with meta data"),
+        }
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn exec_keeps_failed_command_exit_code_over_send_failure() {
+        let mut config = Config::default();
+        config.host = "127.0.0.1:1".to_string();
+        config.metric = Some("lukas.tests.job".to_string());
+        config.metadata_cache = None;
+        let client = config.client();
+
+        for &(command, exit_code) in &[("true", 2), ("false", 1)] {
+            let cli_args = build_cli().get_matches_from(vec!["emit_bosun", "exec", command]);
+            let outcome = exec_command(&config, &client, cli_args.subcommand_matches("exec").unwrap(), false);
+            assert!(outcome.error.is_some());
+            assert_eq!(outcome.exit_code, exit_code);
+        }
+    }
+}
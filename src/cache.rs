@@ -0,0 +1,186 @@
+//! Remembers which metric meta data has been sent successfully in order to not send it again.
+//!
+//! Each `BosunClient` has an in-memory cache. A cache backed by a file keeps the meta data sent for a
+//! limited time across invocations, e.g., of a cron job.
+//!
+//! # Example
+//!
+//! ```no_run
+//! use bosun_emitter::{BosunClient, Metadata};
+//! use bosun_emitter::cache::MetadataCache;
+//!
+//! let mut client = BosunClient::new("localhost:8070", 5);
+//! client.metadata_cache = MetadataCache::with_file("/var/cache/emit_bosun/metadata", 24 * 60 * 60);
+//!
+//! let metadata = Metadata::new("lukas.tests.count", "counter", "Tests", "Amount of Lukas Tests");
+//! // Sends meta data only if it has not been sent within the last day
+//! let _ = client.emit_metadata(&metadata);
+//! ```
+
+use std::cmp;
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::path::PathBuf;
+use std::process;
+use std::sync::Mutex;
+
+use super::{Metadata, now_in_ms};
+
+/// Meta data sent successfully, keyed by metric and a hash of its meta data.
+#[derive(Debug, Default)]
+pub struct MetadataCache {
+    sent: Mutex<HashMap<String, i64>>,
+    /// How long sent meta data is remembered in seconds; forever if not set
+    ttl: Option<u64>,
+    file: Option<PathBuf>,
+}
+
+impl MetadataCache {
+    /// Creates an empty in-memory cache.
+    pub fn new() -> MetadataCache {
+        MetadataCache::default()
+    }
+
+    /// Creates a cache backed by a file that remembers meta data for `ttl` seconds.
+    ///
+    /// A missing or unreadable file yields an empty cache.
+    pub fn with_file<P: Into<PathBuf>>(file: P, ttl: u64) -> MetadataCache {
+        let file = file.into();
+        let sent = MetadataCache::read(&file, ttl);
+
+        MetadataCache {
+            sent: Mutex::new(sent),
+            ttl: Some(ttl),
+            file: Some(file),
+        }
+    }
+
+    /// Returns the cache key of meta data.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use bosun_emitter::Metadata;
+    /// # use bosun_emitter::cache::MetadataCache;
+    /// let metadata = Metadata::new("lukas.tests.count", "counter", "Tests", "Amount of Lukas Tests");
+    ///
+    /// assert!(MetadataCache::key(&metadata).starts_with("lukas.tests.count:"));
+    /// ```
+    pub fn key(metadata: &Metadata) -> String {
+        // FNV-1a, because it is stable across Rust releases in contrast to `DefaultHasher`
+        let mut hash: u64 = 0xcbf29ce484222325;
+        for field in &[metadata.rate, metadata.unit, metadata.description] {
+            for byte in field.bytes().chain(Some(0u8)) {
+                hash ^= byte as u64;
+                hash = hash.wrapping_mul(0x100000001b3);
+            }
+        }
+
+        format!("{}:{:016x}", metadata.metric, hash)
+    }
+
+    /// Checks if exactly this meta data has been sent recently.
+    pub fn contains(&self, metadata: &Metadata) -> bool {
+        let sent = self.sent.lock().unwrap();
+        match (sent.get(&MetadataCache::key(metadata)), self.ttl) {
+            (Some(&sent_at), Some(ttl)) => is_recent(now_in_ms(), sent_at, ttl),
+            (Some(_), None) => true,
+            (None, _) => false,
+        }
+    }
+
+    /// Remembers meta data as sent; a file backed cache is written immediately.
+    ///
+    /// Meta data recorded in the file by other invocations in the meantime is kept.
+    pub fn insert(&self, metadata: &[Metadata]) {
+        let now = now_in_ms();
+        let mut sent = self.sent.lock().unwrap();
+        for m in metadata {
+            sent.insert(MetadataCache::key(m), now);
+        }
+        if let (Some(ref file), Some(ttl)) = (self.file.as_ref(), self.ttl) {
+            for (key, sent_at) in MetadataCache::read(file, ttl) {
+                let entry = sent.entry(key).or_insert(sent_at);
+                if *entry < sent_at {
+                    *entry = sent_at;
+                }
+            }
+            if let Err(err) = MetadataCache::write(file, &sent) {
+                warn!("Failed to write meta data cache '{}', because {}.", file.display(), err);
+            }
+        }
+    }
+
+    /// Returns the names of the metrics whose meta data has been sent, e.g., to complete metric names.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use bosun_emitter::Metadata;
+    /// # use bosun_emitter::cache::MetadataCache;
+    /// let cache = MetadataCache::new();
+    /// cache.insert(&[Metadata::new("lukas.tests.count", "counter", "Tests", "Amount of Lukas Tests"),
+    ///                Metadata::new("lukas.tests.count", "gauge", "Tests", "Amount of Lukas Tests")]);
+    ///
+    /// assert_eq!(cache.metrics(), vec!["lukas.tests.count".to_string()]);
+    /// ```
+    pub fn metrics(&self) -> Vec<String> {
+        let sent = self.sent.lock().unwrap();
+        let mut metrics: Vec<String> = sent.keys()
+            .filter_map(|key| key.rfind(':').map(|end| &key[..end]))
+            .map(|metric| metric.to_string())
+            .collect();
+        metrics.sort();
+        metrics.dedup();
+
+        metrics
+    }
+
+    /// Forgets all meta data sent.
+    pub fn clear(&self) {
+        self.sent.lock().unwrap().clear();
+    }
+
+    /// Reads the meta data sent within the last `ttl` seconds; a missing or unreadable file is empty.
+    fn read(file: &PathBuf, ttl: u64) -> HashMap<String, i64> {
+        let now = now_in_ms();
+        let mut sent = HashMap::new();
+        let mut content = String::new();
+        if File::open(file).and_then(|mut f| f.read_to_string(&mut content)).is_ok() {
+            for line in content.lines() {
+                let mut fields = line.splitn(2, ' ');
+                if let (Some(Ok(sent_at)), Some(key)) = (fields.next().map(|t| t.parse::<i64>()), fields.next()) {
+                    if is_recent(now, sent_at, ttl) {
+                        sent.insert(key.to_string(), sent_at);
+                    }
+                }
+            }
+        }
+
+        sent
+    }
+
+    fn write(file: &PathBuf, sent: &HashMap<String, i64>) -> ::std::io::Result<()> {
+        if let Some(dir) = file.parent() {
+            try!(fs::create_dir_all(dir));
+        }
+        let mut entries: Vec<(&String, &i64)> = sent.iter().collect();
+        entries.sort();
+        // Write atomically, so concurrent invocations never read a partial file
+        let tmp_file = file.with_extension(format!("{}.tmp", process::id()));
+        {
+            let mut f = try!(File::create(&tmp_file));
+            for (key, sent_at) in entries {
+                try!(writeln!(f, "{} {}", sent_at, key));
+            }
+        }
+        fs::rename(&tmp_file, file)
+    }
+}
+
+/// Checks if meta data sent at `sent_at` is younger than `ttl` seconds; a huge `ttl` means forever.
+fn is_recent(now: i64, sent_at: i64, ttl: u64) -> bool {
+    let ttl_in_ms = cmp::min(ttl.saturating_mul(1000), i64::max_value() as u64) as i64;
+    now.saturating_sub(sent_at) < ttl_in_ms
+}
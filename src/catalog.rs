@@ -0,0 +1,241 @@
+//! A catalog declares metric meta data once instead of passing it along with each datum.
+//!
+//! Catalog files are TOML files, usually in `/etc/bosun/metrics.d`, with one `[[metric]]` table
+//! per metric. The name may contain `*` as wildcard to declare meta data for several metrics.
+//!
+//! ```toml
+//! [[metric]]
+//! name = "backup.runtime"
+//! rate = "gauge"
+//! unit = "sec"
+//! description = "Runtime of backups"
+//!
+//! [[metric]]
+//! name = "backup.*.bytes"
+//! rate = "gauge"
+//! unit = "bytes"
+//! description = "Bytes written by backups"
+//! ```
+//!
+//! # Example
+//!
+//! ```no_run
+//! use bosun_emitter::BosunClient;
+//! use bosun_emitter::catalog::Catalog;
+//!
+//! let catalog = Catalog::load_dir("/etc/bosun/metrics.d").unwrap();
+//! let metadata = catalog.lookup("backup.runtime").unwrap();
+//! assert_eq!(metadata.unit, "sec");
+//!
+//! let client = BosunClient::new("localhost:8070", 5);
+//! let _ = client.emit_metadata_list(&catalog.metadata());
+//! ```
+
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use toml;
+
+use super::{Metadata, is_valid_name};
+
+/// Default directory of catalog files
+pub static DEFAULT_CATALOG_DIR: &'static str = "/etc/bosun/metrics.d";
+
+/// Meta data declared for a metric name or pattern.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CatalogEntry {
+    /// Metric name; may contain `*` as wildcard
+    pub name: String,
+    /// Metric rate type: [gauge, counter rate]
+    pub rate: String,
+    /// Metric unit
+    pub unit: String,
+    /// Metric description
+    pub description: String,
+    /// File the entry has been loaded from
+    pub file: Option<PathBuf>,
+}
+
+impl CatalogEntry {
+    /// Checks if the entry declares meta data for several metrics.
+    pub fn is_pattern(&self) -> bool {
+        self.name.contains('*')
+    }
+
+    fn matches(&self, metric: &str) -> bool {
+        matches_pattern(self.name.as_bytes(), metric.as_bytes())
+    }
+
+    /// More specific entries have more characters that must match literally.
+    fn specificity(&self) -> (usize, bool) {
+        (self.name.chars().filter(|&c| c != '*').count(), !self.is_pattern())
+    }
+}
+
+fn matches_pattern(pattern: &[u8], name: &[u8]) -> bool {
+    match pattern.split_first() {
+        None => name.is_empty(),
+        Some((&b'*', rest)) => (0..name.len() + 1).any(|skip| matches_pattern(rest, &name[skip..])),
+        Some((c, rest)) => name.first() == Some(c) && matches_pattern(rest, &name[1..]),
+    }
+}
+
+/// Metric meta data declared in catalog files.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Catalog {
+    entries: Vec<CatalogEntry>,
+}
+
+impl Catalog {
+    /// Creates an empty catalog.
+    pub fn new() -> Catalog {
+        Catalog { entries: Vec::new() }
+    }
+
+    /// Loads all `*.toml` files of a directory in alphabetical order; a missing directory yields an
+    /// empty catalog.
+    pub fn load_dir<P: AsRef<Path>>(dir: P) -> Result<Catalog, Box<Error>> {
+        let dir = dir.as_ref();
+        let mut catalog = Catalog::new();
+        if !dir.exists() {
+            return Ok(catalog);
+        }
+
+        let mut files = Vec::new();
+        for entry in try!(fs::read_dir(dir)) {
+            let path = try!(entry).path();
+            if path.extension().map(|e| e == "toml").unwrap_or(false) {
+                files.push(path);
+            }
+        }
+        files.sort();
+        for file in files {
+            try!(catalog.load_file(&file));
+        }
+
+        Ok(catalog)
+    }
+
+    /// Loads a catalog file and adds its entries.
+    pub fn load_file(&mut self, file_path: &Path) -> Result<(), Box<Error>> {
+        let mut content = String::new();
+        try!(File::open(file_path).and_then(|mut f| f.read_to_string(&mut content)));
+        let mut entries = try!(Catalog::parse(&content)
+            .map_err(|e| format!("failed to load '{}', because {}", file_path.display(), e)));
+        for entry in &mut entries {
+            entry.file = Some(file_path.to_path_buf());
+        }
+        self.entries.extend(entries);
+
+        Ok(())
+    }
+
+    /// Parses catalog entries from TOML.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use bosun_emitter::catalog::Catalog;
+    /// let toml = r#"
+    /// [[metric]]
+    /// name = "backup.*"
+    /// rate = "gauge"
+    /// unit = "sec"
+    /// description = "Backup runtime"
+    /// "#;
+    /// let mut catalog = Catalog::new();
+    /// catalog.add(Catalog::parse(toml).unwrap());
+    ///
+    /// assert_eq!(catalog.lookup("backup.mongodb").unwrap().description, "Backup runtime");
+    /// ```
+    pub fn parse(content: &str) -> Result<Vec<CatalogEntry>, String> {
+        let mut parser = toml::Parser::new(content);
+        let toml = match parser.parse() {
+            Some(toml) => toml,
+            None => {
+                let error = parser.errors.pop().unwrap();
+                let line = content[..error.lo].lines().count().max(1);
+                return Err(format!("line {}: {}", line, error.desc.replace('\n', "\\n")));
+            }
+        };
+        if let Some(key) = toml.keys().find(|&k| k != "metric") {
+            return Err(format!("unknown setting '{}'", key));
+        }
+        let metrics = match toml.get("metric") {
+            Some(&toml::Value::Array(ref metrics)) => metrics.clone(),
+            Some(_) => return Err("'metric' must be an array of tables, i.e., [[metric]]".to_string()),
+            None => Vec::new(),
+        };
+
+        let mut entries = Vec::new();
+        for (index, metric) in metrics.iter().enumerate() {
+            let table = try!(metric.as_table().ok_or_else(|| format!("metric {} is not a table", index + 1)));
+            let field = |name: &str| -> Result<String, String> {
+                table.get(name)
+                     .and_then(|v| v.as_str())
+                     .map(|v| v.to_string())
+                     .ok_or_else(|| format!("metric {} has no {}", index + 1, name))
+            };
+            let entry = CatalogEntry {
+                name: try!(field("name")),
+                rate: try!(field("rate")),
+                unit: try!(field("unit")),
+                description: try!(field("description")),
+                file: None,
+            };
+            if !is_valid_name(&entry.name.replace('*', "x")) {
+                return Err(format!("invalid metric name '{}'", entry.name));
+            }
+            if !["gauge", "counter", "rate"].contains(&entry.rate.as_str()) {
+                return Err(format!("rate of '{}' must be gauge, counter, or rate, but is '{}'",
+                                   entry.name,
+                                   entry.rate));
+            }
+            entries.push(entry);
+        }
+
+        Ok(entries)
+    }
+
+    /// Adds entries; on equal specificity, entries added earlier take precedence.
+    pub fn add(&mut self, entries: Vec<CatalogEntry>) {
+        self.entries.extend(entries);
+    }
+
+    /// Returns all entries.
+    pub fn entries(&self) -> &[CatalogEntry] {
+        &self.entries
+    }
+
+    /// Checks if the catalog has no entries.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Returns the most specific entry matching a metric name; exact names win over patterns.
+    pub fn find(&self, metric: &str) -> Option<&CatalogEntry> {
+        let mut found: Option<&CatalogEntry> = None;
+        for entry in self.entries.iter().filter(|e| e.matches(metric)) {
+            if found.map(|f| entry.specificity() > f.specificity()).unwrap_or(true) {
+                found = Some(entry);
+            }
+        }
+
+        found
+    }
+
+    /// Looks up the meta data of a metric.
+    pub fn lookup<'a>(&'a self, metric: &'a str) -> Option<Metadata<'a>> {
+        self.find(metric).map(|e| Metadata::new(metric, &e.rate, &e.unit, &e.description))
+    }
+
+    /// Returns the meta data of all entries which are not patterns, e.g., to send them to Bosun.
+    pub fn metadata<'a>(&'a self) -> Vec<Metadata<'a>> {
+        self.entries
+            .iter()
+            .filter(|e| !e.is_pattern())
+            .map(|e| Metadata::new(&e.name, &e.rate, &e.unit, &e.description))
+            .collect()
+    }
+}
@@ -0,0 +1,201 @@
+//! Runs a command and measures its runtime, exit code, and resource usage.
+//!
+//! The command inherits stdin, stdout, and stderr. While it runs, termination and user signals sent
+//! to this process are forwarded to the command; interrupts from the terminal reach the command
+//! directly, because it runs in the same process group.
+//!
+//! # Example
+//!
+//! ```no_run
+//! use bosun_emitter::{BosunClient, Datum, now_in_ms};
+//! use bosun_emitter::exec;
+//!
+//! let execution = exec::run("/usr/local/bin/backup.sh", &[]).unwrap();
+//! let data_points = execution.data_points("backup", now_in_ms(), false);
+//!
+//! let client = BosunClient::new("localhost:8070", 5);
+//! let data: Vec<Datum> = data_points.iter().map(|d| d.as_datum()).collect();
+//! let _ = client.emit_data(&data);
+//!
+//! std::process::exit(execution.exit_code);
+//! ```
+
+use std::io;
+use std::process::Command;
+use std::time::{Duration, Instant};
+
+use super::{DataPoint, Tags};
+
+/// Metrics reported for an execution as `(suffix, unit, description)`; the last two require resource usage.
+pub static METRICS: &'static [(&'static str, &'static str, &'static str)] = &[
+    ("runtime", "seconds", "Runtime"),
+    ("exit_code", "code", "Exit code"),
+    ("success", "bool", "Success (1) or failure (0)"),
+    ("max_rss", "bytes", "Peak resident set size"),
+    ("cpu_time", "seconds", "User and system CPU time"),
+];
+
+/// Result of running a command.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Execution {
+    /// Wall clock time between starting the command and its termination
+    pub runtime: Duration,
+    /// Exit code of the command; 128 plus the signal number if it has been killed by a signal
+    pub exit_code: i32,
+    /// Peak resident set size in bytes, if available
+    pub max_rss: Option<u64>,
+    /// CPU time spent in user and system mode, if available
+    pub cpu_time: Option<Duration>,
+}
+
+impl Execution {
+    /// Returns whether the command exited successfully.
+    pub fn success(&self) -> bool {
+        self.exit_code == 0
+    }
+
+    /// Creates data points `<metric>.runtime`, `<metric>.exit_code`, and `<metric>.success`, and
+    /// if `rusage` is set and available, `<metric>.max_rss` and `<metric>.cpu_time`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use std::time::Duration;
+    /// # use bosun_emitter::exec::Execution;
+    /// let execution = Execution { runtime: Duration::from_millis(1500), exit_code: 2, max_rss: None, cpu_time: None };
+    /// let data_points = execution.data_points("backup", 1458066838, true);
+    ///
+    /// assert_eq!(data_points.len(), 3);
+    /// assert_eq!(data_points[0].metric, "backup.runtime");
+    /// assert_eq!(data_points[0].value, "1.500");
+    /// assert_eq!(data_points[1].value, "2");
+    /// assert_eq!(data_points[2].value, "0");
+    /// ```
+    pub fn data_points(&self, metric: &str, timestamp: i64, rusage: bool) -> Vec<DataPoint> {
+        let mut values = vec![("runtime", seconds(&self.runtime)),
+                              ("exit_code", self.exit_code.to_string()),
+                              ("success", (if self.success() { "1" } else { "0" }).to_string())];
+        if rusage {
+            if let Some(max_rss) = self.max_rss {
+                values.push(("max_rss", max_rss.to_string()));
+            }
+            if let Some(ref cpu_time) = self.cpu_time {
+                values.push(("cpu_time", seconds(cpu_time)));
+            }
+        }
+
+        values.into_iter()
+              .map(|(suffix, value)| DataPoint::new(format!("{}.{}", metric, suffix), timestamp, value, Tags::new()))
+              .collect()
+    }
+}
+
+fn seconds(duration: &Duration) -> String {
+    format!("{}.{:03}", duration.as_secs(), duration.subsec_nanos() / 1_000_000)
+}
+
+/// Runs a command to completion.
+pub fn run(program: &str, args: &[String]) -> io::Result<Execution> {
+    let start = Instant::now();
+    let child = try!(Command::new(program).args(args).spawn());
+    let (exit_code, max_rss, cpu_time) = try!(wait(child));
+
+    Ok(Execution {
+        runtime: start.elapsed(),
+        exit_code: exit_code,
+        max_rss: max_rss,
+        cpu_time: cpu_time,
+    })
+}
+
+#[cfg(unix)]
+mod signals {
+    use libc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    static CHILD_PID: AtomicUsize = AtomicUsize::new(0);
+    static FORWARDED_SIGNALS: &'static [libc::c_int] = &[libc::SIGTERM, libc::SIGHUP, libc::SIGUSR1, libc::SIGUSR2];
+    static IGNORED_SIGNALS: &'static [libc::c_int] = &[libc::SIGINT, libc::SIGQUIT];
+
+    extern "C" fn forward(signal: libc::c_int) {
+        let pid = CHILD_PID.load(Ordering::SeqCst);
+        if pid != 0 {
+            unsafe {
+                libc::kill(pid as libc::pid_t, signal);
+            }
+        }
+    }
+
+    /// Forwards signals to the child until dropped.
+    pub struct Forwarder {
+        previous: Vec<(libc::c_int, libc::sighandler_t)>,
+    }
+
+    impl Forwarder {
+        pub fn new(pid: libc::pid_t) -> Forwarder {
+            CHILD_PID.store(pid as usize, Ordering::SeqCst);
+            let mut previous = Vec::new();
+            for &signal in FORWARDED_SIGNALS {
+                previous.push((signal, unsafe { libc::signal(signal, forward as extern "C" fn(libc::c_int) as libc::sighandler_t) }));
+            }
+            for &signal in IGNORED_SIGNALS {
+                previous.push((signal, unsafe { libc::signal(signal, libc::SIG_IGN) }));
+            }
+            Forwarder { previous: previous }
+        }
+    }
+
+    impl Drop for Forwarder {
+        fn drop(&mut self) {
+            for &(signal, handler) in &self.previous {
+                unsafe {
+                    libc::signal(signal, handler);
+                }
+            }
+            CHILD_PID.store(0, Ordering::SeqCst);
+        }
+    }
+}
+
+#[cfg(unix)]
+fn wait(child: ::std::process::Child) -> io::Result<(i32, Option<u64>, Option<Duration>)> {
+    use libc;
+    use std::mem;
+
+    let pid = child.id() as libc::pid_t;
+    let _forwarder = signals::Forwarder::new(pid);
+    let mut status: libc::c_int = 0;
+    let mut rusage: libc::rusage = unsafe { mem::zeroed() };
+    loop {
+        if unsafe { libc::wait4(pid, &mut status, 0, &mut rusage) } == pid {
+            break;
+        }
+        let err = io::Error::last_os_error();
+        if err.kind() != io::ErrorKind::Interrupted {
+            return Err(err);
+        }
+    }
+
+    let exit_code = if libc::WIFSIGNALED(status) {
+        128 + libc::WTERMSIG(status)
+    } else {
+        libc::WEXITSTATUS(status)
+    };
+    // Linux reports kilobytes, macOS bytes
+    let rss_factor = if cfg!(target_os = "macos") { 1 } else { 1024 };
+    let max_rss = rusage.ru_maxrss as u64 * rss_factor;
+    let cpu_time = timeval_to_duration(&rusage.ru_utime) + timeval_to_duration(&rusage.ru_stime);
+
+    Ok((exit_code, Some(max_rss), Some(cpu_time)))
+}
+
+#[cfg(unix)]
+fn timeval_to_duration(tv: &::libc::timeval) -> Duration {
+    Duration::new(tv.tv_sec as u64, tv.tv_usec as u32 * 1000)
+}
+
+#[cfg(not(unix))]
+fn wait(mut child: ::std::process::Child) -> io::Result<(i32, Option<u64>, Option<Duration>)> {
+    let status = try!(child.wait());
+    Ok((status.code().unwrap_or(1), None, None))
+}
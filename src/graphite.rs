@@ -0,0 +1,219 @@
+//! Graphite's plaintext protocol, i.e., lines `<path> <value> <timestamp>`, converted to Bosun's
+//! metric names and tags by templates.
+//!
+//! A template `[<filter>] <template> [<tagk1=tagv1>,...]` maps the dot separated parts of paths
+//! matching the filter to the metric name and to tags:
+//!
+//! * `measurement` parts become the metric name, `field` parts are appended to it
+//! * `measurement*` and `field*` take all remaining parts
+//! * any other name becomes a tag key with the part as value; an empty name drops the part
+//!
+//! Filters consist of parts and `*` wildcards. The template with the most specific filter matching
+//! a path applies; a template without filter applies to all other paths. Paths no template applies
+//! to become metric names as they are.
+//!
+//! # Example
+//!
+//! ```
+//! # use bosun_emitter::graphite::Templates;
+//! let templates = Templates::parse(&["servers.*.cpu.* .host.measurement.field dc=eu"]).unwrap();
+//! let data_point = templates.parse_line("servers.web01.cpu.idle 97.5 1458066838").unwrap().unwrap();
+//!
+//! assert_eq!(data_point.metric, "cpu.idle");
+//! assert_eq!(data_point.timestamp, 1458066838);
+//! assert_eq!(data_point.value, "97.5");
+//! assert_eq!(data_point.tags["host"], "web01");
+//! assert_eq!(data_point.tags["dc"], "eu");
+//! ```
+
+use std::io::{self, BufRead};
+use std::sync::mpsc::Sender;
+
+use super::{DataPoint, Tags, now_in_ms, parse_tags};
+
+/// Maps the parts of Graphite paths matching a filter to metric names and tags.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Template {
+    filter: Vec<String>,
+    parts: Vec<String>,
+    tags: Tags,
+}
+
+impl Template {
+    /// Parses a template `[<filter>] <template> [<tagk1=tagv1>,...]`.
+    pub fn parse(template: &str) -> Result<Template, String> {
+        let fields: Vec<&str> = template.split_whitespace().collect();
+        let (filter, parts, tags) = match fields.len() {
+            1 => (None, fields[0], None),
+            2 if fields[1].contains('=') => (None, fields[0], Some(fields[1])),
+            2 => (Some(fields[0]), fields[1], None),
+            3 => (Some(fields[0]), fields[1], Some(fields[2])),
+            _ => return Err(format!("template '{}' is not [<FILTER>] <TEMPLATE> [<TAGS>]", template)),
+        };
+        let parts: Vec<String> = parts.split('.').map(|p| p.to_string()).collect();
+        if !parts.iter().any(|p| p.starts_with("measurement")) {
+            return Err(format!("template '{}' has no measurement", template));
+        }
+        let tags = match tags {
+            Some(tags) => try!(parse_tags(tags).map_err(|e| format!("template '{}' has invalid tags, because {}", template, e))),
+            None => Tags::new(),
+        };
+
+        Ok(Template {
+            filter: filter.map(|f| f.split('.').map(|p| p.to_string()).collect()).unwrap_or_default(),
+            parts: parts,
+            tags: tags,
+        })
+    }
+
+    fn matches(&self, path: &[&str]) -> bool {
+        self.filter.len() <= path.len() && self.filter.iter().zip(path).all(|(f, p)| f == "*" || f == p)
+    }
+
+    /// Number of literal filter parts, and then filter parts, to prefer more specific filters
+    fn specificity(&self) -> (usize, usize) {
+        (self.filter.iter().filter(|f| *f != "*").count(), self.filter.len())
+    }
+
+    /// Returns the metric name and the tags of a path.
+    fn apply(&self, path: &[&str]) -> (String, Tags) {
+        let mut measurement = Vec::new();
+        let mut field = Vec::new();
+        let mut tags: Vec<(String, Vec<&str>)> = Vec::new();
+        for (index, part) in self.parts.iter().enumerate() {
+            if index >= path.len() {
+                break;
+            }
+            let values = if part.ends_with('*') { &path[index..] } else { &path[index..index + 1] };
+            match part.as_str() {
+                "" => {}
+                "measurement" | "measurement*" => measurement.extend_from_slice(values),
+                "field" | "field*" => field.extend_from_slice(values),
+                key => {
+                    let key = key.trim_end_matches('*');
+                    match tags.iter().position(|tag| tag.0 == key) {
+                        Some(position) => tags[position].1.extend_from_slice(values),
+                        None => tags.push((key.to_string(), values.to_vec())),
+                    }
+                }
+            }
+        }
+        measurement.extend(field);
+
+        let mut all_tags = self.tags.clone();
+        for (key, values) in tags {
+            all_tags.insert(key, values.join("."));
+        }
+
+        (measurement.join("."), all_tags)
+    }
+}
+
+/// Templates to convert Graphite paths with.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Templates {
+    templates: Vec<Template>,
+}
+
+impl Templates {
+    /// Parses templates; templates listed first win over templates with equally specific filters.
+    pub fn parse<S: AsRef<str>>(templates: &[S]) -> Result<Templates, String> {
+        let mut parsed = Vec::new();
+        for template in templates {
+            parsed.push(try!(Template::parse(template.as_ref())));
+        }
+
+        Ok(Templates { templates: parsed })
+    }
+
+    /// Returns the metric name and the tags of a Graphite path.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use bosun_emitter::graphite::Templates;
+    /// let templates = Templates::parse(&["stats.* .measurement*", "stats.timers.* ..measurement*"]).unwrap();
+    ///
+    /// assert_eq!(templates.convert("stats.gauges.queue.size").0, "gauges.queue.size");
+    /// assert_eq!(templates.convert("stats.timers.login.time").0, "login.time");
+    /// assert_eq!(templates.convert("backup.runtime").0, "backup.runtime");
+    /// ```
+    pub fn convert(&self, path: &str) -> (String, Tags) {
+        let parts: Vec<&str> = path.split('.').collect();
+        let mut best: Option<&Template> = None;
+        for template in self.templates.iter().filter(|t| t.matches(&parts)) {
+            match best {
+                Some(b) if b.specificity() >= template.specificity() => {}
+                _ => best = Some(template),
+            }
+        }
+        match best {
+            Some(template) => template.apply(&parts),
+            None => (path.to_string(), Tags::new()),
+        }
+    }
+
+    /// Parses a data point from a line `<path> <value> [<timestamp>]`; empty lines yield `None`.
+    ///
+    /// A missing timestamp or `-1` means now.
+    pub fn parse_line(&self, line: &str) -> Result<Option<DataPoint>, String> {
+        let mut fields = line.split_whitespace();
+        let path = match fields.next() {
+            Some(path) => path,
+            None => return Ok(None),
+        };
+        let value = try!(fields.next().ok_or("missing value"));
+        if value.parse::<f64>().is_err() {
+            return Err(format!("invalid value '{}'", value));
+        }
+        let timestamp = match fields.next() {
+            None | Some("-1") => now_in_ms(),
+            Some(timestamp) => try!(timestamp.parse::<i64>().map_err(|_| format!("invalid timestamp '{}'", timestamp))),
+        };
+        if let Some(field) = fields.next() {
+            return Err(format!("unexpected '{}' after timestamp", field));
+        }
+
+        let (metric, tags) = self.convert(path);
+        let data_point = DataPoint::new(metric, timestamp, value, tags);
+        try!(data_point.validate());
+
+        Ok(Some(data_point))
+    }
+}
+
+/// Reads data points in Graphite's plaintext protocol from a client and passes them on.
+///
+/// Graphite's protocol has no replies, so malformed lines are only logged. Returns the number of
+/// data points accepted.
+///
+/// # Example
+///
+/// ```
+/// # use std::sync::mpsc;
+/// # use bosun_emitter::graphite::{self, Templates};
+/// let (sender, receiver) = mpsc::channel();
+/// let input = "servers.web01.load 0.5 1458066838\nservers.web01.load\n";
+/// let templates = Templates::parse(&[".host.measurement"]).unwrap();
+///
+/// assert_eq!(graphite::serve(input.as_bytes(), &sender, &templates).unwrap(), 1);
+/// assert_eq!(receiver.recv().unwrap().tags["host"], "web01");
+/// ```
+pub fn serve<R: BufRead>(reader: R, sender: &Sender<DataPoint>, templates: &Templates) -> io::Result<usize> {
+    let mut accepted = 0;
+    for (index, line) in reader.lines().enumerate() {
+        let line = try!(line);
+        match templates.parse_line(&line) {
+            Ok(Some(data_point)) => {
+                if sender.send(data_point).is_err() {
+                    return Err(io::Error::new(io::ErrorKind::BrokenPipe, "agent stopped"));
+                }
+                accepted += 1;
+            }
+            Ok(None) => {}
+            Err(e) => debug!("Skipping malformed Graphite line {}, because {}.", index + 1, e),
+        }
+    }
+
+    Ok(accepted)
+}
@@ -0,0 +1,434 @@
+//! Imports data points from CSV files or JSON objects, e.g., to backfill historical data exported
+//! by other tools.
+//!
+//! A `Mapping` names the columns holding metric names, timestamps, and values as well as the
+//! columns that become tags. CSV files start with a header; columns are referred to by name or by
+//! number starting at 1. Quoted CSV fields may span up to 100 lines. JSON files contain one object
+//! per line; columns are their keys.
+//!
+//! # Example
+//!
+//! ```
+//! # use bosun_emitter::import::{Column, Format, Import, Mapping};
+//! let csv = "date,job,size\n2016-03-15,daily,1024\n2016-03-16,daily,oops\n";
+//! let mut mapping = Mapping::default();
+//! mapping.metric_name = Some("backup.size".to_string());
+//! mapping.timestamp = Column::parse("date");
+//! mapping.timestamp_format = Some("%Y-%m-%d".to_string());
+//! mapping.value = Column::parse("3");
+//! mapping.tags.push(Mapping::parse_tag("job=type").unwrap());
+//!
+//! let records: Vec<_> = Import::new(csv.as_bytes(), Format::Csv(','), mapping).map(|r| r.unwrap()).collect();
+//!
+//! let (line, ref data_point) = records[0];
+//! let data_point = data_point.as_ref().unwrap();
+//! assert_eq!(line, 2);
+//! assert_eq!(data_point.metric, "backup.size");
+//! assert_eq!(data_point.timestamp, 1458000000000);
+//! assert_eq!(data_point.tags["type"], "daily");
+//! assert_eq!(records[1], (3, Err("invalid value 'oops'".to_string())));
+//! ```
+
+use std::collections::VecDeque;
+use std::io::{self, BufRead};
+use std::str::FromStr;
+
+use chrono::{DateTime, NaiveDate, NaiveDateTime};
+use rustc_serialize::json::Json;
+
+use super::{DataPoint, Tags, now_in_ms, parse_timestamp, timestamp_to_ms};
+
+/// Supported file formats
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    /// Comma separated values with a header and the delimiter given
+    Csv(char),
+    /// One JSON object per line
+    Json,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Format, String> {
+        match s {
+            "csv" => Ok(Format::Csv(',')),
+            "json" => Ok(Format::Json),
+            _ => Err(format!("unknown import format '{}'", s)),
+        }
+    }
+}
+
+/// Column of a record, referred to by name or by number starting at 1
+#[derive(Debug, Clone, PartialEq)]
+pub enum Column {
+    /// Column name, i.e., CSV header field or JSON key
+    Name(String),
+    /// Column number starting at 1; only for CSV, because JSON keys are not ordered
+    Index(usize),
+}
+
+impl Column {
+    /// Parses positive numbers as column numbers and everything else as column names.
+    pub fn parse(column: &str) -> Column {
+        match column.parse::<usize>() {
+            Ok(index) if index > 0 => Column::Index(index),
+            _ => Column::Name(column.to_string()),
+        }
+    }
+}
+
+/// Maps the columns of records to the parts of data points.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Mapping {
+    /// Column of metric names; not used if `metric_name` is set
+    pub metric: Column,
+    /// Metric name of all data points
+    pub metric_name: Option<String>,
+    /// Column of timestamps
+    pub timestamp: Column,
+    /// Format of timestamps like `%Y-%m-%d %H:%M:%S` in UTC unless it contains `%z`; Unix timestamps in
+    /// seconds or ms and RFC 3339 if not set
+    pub timestamp_format: Option<String>,
+    /// Column of values
+    pub value: Column,
+    /// Columns that become tags with their tag keys
+    pub tags: Vec<(Column, String)>,
+}
+
+impl Default for Mapping {
+    /// Maps the columns `metric`, `timestamp`, and `value`.
+    fn default() -> Mapping {
+        Mapping {
+            metric: Column::parse("metric"),
+            metric_name: None,
+            timestamp: Column::parse("timestamp"),
+            timestamp_format: None,
+            value: Column::parse("value"),
+            tags: Vec::new(),
+        }
+    }
+}
+
+impl Mapping {
+    /// Parses a tag column `<COLUMN>[=<TAG>]`; without tag key, the column name is the tag key.
+    pub fn parse_tag(tag: &str) -> Result<(Column, String), String> {
+        let mut kv = tag.splitn(2, '=');
+        match (kv.next(), kv.next()) {
+            (Some(column), Some(key)) if !column.is_empty() && !key.is_empty() => {
+                Ok((Column::parse(column), key.to_string()))
+            }
+            (Some(column), None) if !column.is_empty() => {
+                match Column::parse(column) {
+                    Column::Index(_) => Err(format!("tag column '{}' is a number and needs a tag key", tag)),
+                    name => Ok((name, column.to_string())),
+                }
+            }
+            _ => Err(format!("tag column '{}' is not <COLUMN>[=<TAG>]", tag)),
+        }
+    }
+
+    /// Returns all columns the mapping uses.
+    fn columns(&self) -> Vec<&Column> {
+        let mut columns = vec![&self.timestamp, &self.value];
+        if self.metric_name.is_none() {
+            columns.push(&self.metric);
+        }
+        columns.extend(self.tags.iter().map(|tag| &tag.0));
+
+        columns
+    }
+
+    /// Converts a record to a data point; empty tag values are dropped.
+    fn data_point(&self, record: &[(String, String)]) -> Result<DataPoint, String> {
+        let field = |column: &Column| -> Result<&str, String> {
+            let field = match *column {
+                Column::Name(ref name) => record.iter().find(|f| f.0 == *name),
+                Column::Index(index) => index.checked_sub(1).and_then(|index| record.get(index)),
+            };
+            match field {
+                Some((_, value)) if !value.is_empty() => Ok(value.as_str()),
+                _ => Err(format!("missing {}", describe(column))),
+            }
+        };
+
+        let metric = match self.metric_name {
+            Some(ref metric) => metric.as_str(),
+            None => try!(field(&self.metric)),
+        };
+        let timestamp = try!(parse_time(try!(field(&self.timestamp)), self.timestamp_format.as_ref()));
+        let value = try!(field(&self.value));
+        let mut tags = Tags::new();
+        for (column, key) in &self.tags {
+            if let Ok(value) = field(column) {
+                tags.insert(key.clone(), value.to_string());
+            }
+        }
+
+        let data_point = DataPoint::new(metric, timestamp, value, tags);
+        try!(data_point.validate());
+
+        Ok(data_point)
+    }
+}
+
+fn describe(column: &Column) -> String {
+    match *column {
+        Column::Name(ref name) => format!("column '{}'", name),
+        Column::Index(index) => format!("column {}", index),
+    }
+}
+
+/// Parses a timestamp by a format, or as Unix timestamp or RFC 3339 without format; returns ms.
+fn parse_time(value: &str, format: Option<&String>) -> Result<i64, String> {
+    let format = match format {
+        Some(format) => format,
+        None => return parse_timestamp(value, now_in_ms()),
+    };
+    let ms = if let Ok(datetime) = DateTime::parse_from_str(value, format) {
+        datetime.timestamp() * 1000 + datetime.timestamp_subsec_millis() as i64
+    } else if let Ok(datetime) = NaiveDateTime::parse_from_str(value, format) {
+        datetime.timestamp() * 1000 + datetime.timestamp_subsec_millis() as i64
+    } else if let Ok(date) = NaiveDate::parse_from_str(value, format) {
+        date.and_hms(0, 0, 0).timestamp() * 1000
+    } else {
+        return Err(format!("timestamp '{}' does not match format '{}'", value, format));
+    };
+
+    timestamp_to_ms(ms)
+}
+
+/// Maximum number of lines of a CSV record, so an unterminated quote does not swallow the rest of a file
+const MAX_RECORD_LINES: usize = 100;
+
+/// Record read from a line, or the reason it is malformed, with its line number
+type Line<T> = io::Result<(usize, Result<T, String>)>;
+
+/// Reads records and converts them to data points; yields each record's line number with its data
+/// point or the reason it is malformed.
+///
+/// Fails with `InvalidInput` if the CSV header lacks a column of the mapping or if JSON columns are
+/// referred to by number.
+pub struct Import<R> {
+    lines: io::Lines<R>,
+    /// Lines read ahead that are read again, e.g., after an unterminated quoted field
+    pending: VecDeque<String>,
+    line: usize,
+    format: Format,
+    mapping: Mapping,
+    header: Option<Vec<String>>,
+}
+
+impl<R: BufRead> Import<R> {
+    /// Creates an import reading from `reader`.
+    pub fn new(reader: R, format: Format, mapping: Mapping) -> Import<R> {
+        Import {
+            lines: reader.lines(),
+            pending: VecDeque::new(),
+            line: 0,
+            format: format,
+            mapping: mapping,
+            header: None,
+        }
+    }
+
+    /// Returns the number of lines read so far.
+    pub fn line(&self) -> usize {
+        self.line
+    }
+
+    /// Reads the next line, which may have been read ahead before.
+    fn next_raw_line(&mut self) -> Option<io::Result<String>> {
+        let line = match self.pending.pop_front() {
+            Some(line) => Some(Ok(line)),
+            None => self.lines.next(),
+        };
+        if let Some(Ok(_)) = line {
+            self.line += 1;
+        }
+
+        line
+    }
+
+    /// Reads the next non-empty line and returns its line number.
+    fn next_line(&mut self) -> Option<io::Result<(usize, String)>> {
+        while let Some(line) = self.next_raw_line() {
+            match line {
+                Ok(ref line) if line.trim().is_empty() => {}
+                Ok(line) => return Some(Ok((self.line, line))),
+                Err(e) => return Some(Err(e)),
+            }
+        }
+
+        None
+    }
+
+    /// Reads a CSV record, which may span several lines if quoted fields contain line breaks.
+    ///
+    /// If a quoted field is not terminated within `MAX_RECORD_LINES` lines or at the end of the
+    /// input, the record's first line is malformed and reading continues with its second line.
+    fn next_csv_record(&mut self, delimiter: char) -> Option<Line<Vec<String>>> {
+        let (start, mut record) = match self.next_line() {
+            Some(Ok(line)) => line,
+            Some(Err(e)) => return Some(Err(e)),
+            None => return None,
+        };
+        let mut continued = Vec::new();
+        loop {
+            if let Some(fields) = split_csv(&record, delimiter) {
+                return Some(Ok((start, Ok(fields))));
+            }
+            if continued.len() + 1 < MAX_RECORD_LINES {
+                match self.next_raw_line() {
+                    Some(Ok(line)) => {
+                        record.push('\n');
+                        record.push_str(&line);
+                        continued.push(line);
+                        continue;
+                    }
+                    Some(Err(e)) => return Some(Err(e)),
+                    None => {}
+                }
+            }
+            let err = match continued.len() {
+                0 => "unterminated quoted field".to_string(),
+                n => format!("unterminated quoted field within {} lines", n + 1),
+            };
+            self.line -= continued.len();
+            for line in continued.into_iter().rev() {
+                self.pending.push_front(line);
+            }
+            return Some(Ok((start, Err(err))));
+        }
+    }
+
+    /// Reads and checks the CSV header.
+    fn read_header(&mut self, delimiter: char) -> io::Result<Option<Vec<String>>> {
+        let header = match self.next_csv_record(delimiter) {
+            Some(Ok((_, Ok(header)))) => header,
+            Some(Ok((line, Err(e)))) => {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("header in line {}: {}", line, e)))
+            }
+            Some(Err(e)) => return Err(e),
+            None => return Ok(None),
+        };
+        for column in self.mapping.columns() {
+            let found = match *column {
+                Column::Name(ref name) => header.contains(name),
+                Column::Index(index) => index > 0 && index <= header.len(),
+            };
+            if !found {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                          format!("the header has no {}", describe(column))));
+            }
+        }
+
+        Ok(Some(header))
+    }
+
+    /// Checks that JSON columns are referred to by name.
+    fn check_json_columns(&self) -> io::Result<()> {
+        match self.mapping.columns().into_iter().find(|column| match **column {
+            Column::Index(_) => true,
+            Column::Name(_) => false,
+        }) {
+            Some(column) => {
+                Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                   format!("JSON has no {}; refer to JSON keys by name", describe(column))))
+            }
+            None => Ok(()),
+        }
+    }
+}
+
+impl<R: BufRead> Iterator for Import<R> {
+    type Item = Line<DataPoint>;
+
+    fn next(&mut self) -> Option<Line<DataPoint>> {
+        let (line, record) = match self.format {
+            Format::Csv(delimiter) => {
+                if self.header.is_none() {
+                    match self.read_header(delimiter) {
+                        Ok(Some(header)) => self.header = Some(header),
+                        Ok(None) => return None,
+                        Err(e) => return Some(Err(e)),
+                    }
+                }
+                let (line, fields) = match self.next_csv_record(delimiter) {
+                    Some(Ok(record)) => record,
+                    Some(Err(e)) => return Some(Err(e)),
+                    None => return None,
+                };
+                // unwrap is safe, because the header has been read
+                let header = self.header.as_ref().unwrap();
+                (line, fields.map(|fields| header.iter().cloned().zip(fields).collect()))
+            }
+            Format::Json => {
+                if let Err(e) = self.check_json_columns() {
+                    return Some(Err(e));
+                }
+                let (line, text) = match self.next_line() {
+                    Some(Ok(line)) => line,
+                    Some(Err(e)) => return Some(Err(e)),
+                    None => return None,
+                };
+                (line, json_record(&text))
+            }
+        };
+
+        Some(Ok((line, record.and_then(|record: Vec<(String, String)>| self.mapping.data_point(&record)))))
+    }
+}
+
+/// Splits a CSV record; quoted fields may contain delimiters, line breaks, and `""` as quote.
+/// Returns `None` if a quoted field is not terminated.
+fn split_csv(record: &str, delimiter: char) -> Option<Vec<String>> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = record.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                let _ = chars.next();
+                field.push('"');
+            }
+            '"' if quoted => quoted = false,
+            '"' if field.trim().is_empty() => {
+                field.clear();
+                quoted = true;
+            }
+            _ if c == delimiter && !quoted => {
+                fields.push(field.trim().to_string());
+                field.clear();
+            }
+            _ => field.push(c),
+        }
+    }
+    if quoted {
+        return None;
+    }
+    fields.push(field.trim().to_string());
+
+    Some(fields)
+}
+
+/// Returns the fields of a JSON object; numbers and booleans become strings, `null` an empty field.
+fn json_record(text: &str) -> Result<Vec<(String, String)>, String> {
+    let object = match Json::from_str(text) {
+        Ok(Json::Object(object)) => object,
+        Ok(_) => return Err("not a JSON object".to_string()),
+        Err(e) => return Err(format!("invalid JSON, because {}", e)),
+    };
+
+    Ok(object.into_iter()
+        .map(|(key, value)| {
+            let value = match value {
+                Json::String(s) => s,
+                Json::Null => String::new(),
+                other => other.to_string(),
+            };
+            (key, value)
+        })
+        .collect())
+}
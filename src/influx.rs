@@ -0,0 +1,186 @@
+//! InfluxDB's line protocol as written by Telegraf, i.e., lines
+//! `<measurement>[,<tagk>=<tagv>...] <field>=<value>[,<field>=<value>...] [<timestamp>]`.
+//!
+//! Each numeric or boolean field becomes one data point `<measurement>.<field>` with the line's tags;
+//! booleans become 1 or 0, and string fields are skipped, because Bosun only stores numbers.
+
+use std::str::FromStr;
+
+use super::{DataPoint, Tags, now_in_ms};
+
+/// Unit of the timestamps
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Precision {
+    /// Nanoseconds, InfluxDB's default
+    Nanoseconds,
+    /// Microseconds
+    Microseconds,
+    /// Milliseconds
+    Milliseconds,
+    /// Seconds
+    Seconds,
+}
+
+impl Precision {
+    /// Converts a timestamp of this precision to ms; `None` if it is out of range.
+    pub fn to_ms(&self, timestamp: i64) -> Option<i64> {
+        match *self {
+            Precision::Nanoseconds => Some(timestamp / 1_000_000),
+            Precision::Microseconds => Some(timestamp / 1_000),
+            Precision::Milliseconds => Some(timestamp),
+            Precision::Seconds => timestamp.checked_mul(1_000),
+        }
+    }
+}
+
+impl FromStr for Precision {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Precision, String> {
+        match s {
+            "ns" | "n" => Ok(Precision::Nanoseconds),
+            "us" | "u" => Ok(Precision::Microseconds),
+            "ms" => Ok(Precision::Milliseconds),
+            "s" => Ok(Precision::Seconds),
+            _ => Err(format!("unknown precision '{}'", s)),
+        }
+    }
+}
+
+/// Parses the data points of all fields of a line; empty lines and comments starting with `#` yield
+/// no data points.
+///
+/// A missing timestamp means now.
+///
+/// # Example
+///
+/// ```
+/// # use bosun_emitter::influx::{self, Precision};
+/// let data = influx::parse_line(r#"disk,host=web01,path=/var used=42i,free=57.5,ok=true,mode="rw" 1458066838000000000"#,
+///                               Precision::Nanoseconds)
+///     .unwrap();
+///
+/// assert_eq!(data.len(), 3);
+/// assert_eq!(data[0].metric, "disk.used");
+/// assert_eq!(data[0].value, "42");
+/// assert_eq!(data[0].timestamp, 1458066838000);
+/// assert_eq!(data[0].tags["path"], "/var");
+/// assert_eq!(data[1].metric, "disk.free");
+/// assert_eq!(data[2].value, "1");
+/// ```
+pub fn parse_line(line: &str, precision: Precision) -> Result<Vec<DataPoint>, String> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+        return Ok(Vec::new());
+    }
+
+    let sections = split_unescaped(line, ' ');
+    let (series, fields, timestamp) = match sections.len() {
+        2 => (&sections[0], &sections[1], None),
+        3 => (&sections[0], &sections[1], Some(&sections[2])),
+        1 => return Err("missing fields".to_string()),
+        _ => return Err(format!("unexpected '{}' after timestamp", sections[3])),
+    };
+    let timestamp = match timestamp {
+        Some(timestamp) => {
+            let timestamp = try!(timestamp.parse::<i64>().map_err(|_| format!("invalid timestamp '{}'", timestamp)));
+            try!(precision.to_ms(timestamp).ok_or_else(|| "timestamp out of range".to_string()))
+        }
+        None => now_in_ms(),
+    };
+
+    let mut series = split_unescaped(series, ',').into_iter();
+    // unwrap is safe, because splitting yields at least one part
+    let measurement = unescape(&series.next().unwrap());
+    let mut tags = Tags::new();
+    for tag in series {
+        let (key, value) = try!(split_key_value(&tag).ok_or_else(|| format!("invalid tag '{}'", tag)));
+        tags.insert(key, value);
+    }
+
+    let mut data = Vec::new();
+    for field in split_unescaped(fields, ',') {
+        let (key, value) = try!(split_key_value(&field).ok_or_else(|| format!("invalid field '{}'", field)));
+        let value = match try!(field_value(&value).map_err(|e| format!("{} of field '{}'", e, key))) {
+            Some(value) => value,
+            None => continue,
+        };
+        let data_point = DataPoint::new(format!("{}.{}", measurement, key), timestamp, value, tags.clone());
+        try!(data_point.validate());
+        data.push(data_point);
+    }
+    if data.is_empty() {
+        return Err(format!("no numeric fields in '{}'", fields));
+    }
+
+    Ok(data)
+}
+
+/// Returns the numeric value of a field, or `None` for strings.
+fn field_value(value: &str) -> Result<Option<String>, String> {
+    if value.starts_with('"') {
+        return Ok(None);
+    }
+    let number = if value.ends_with('i') || value.ends_with('u') { &value[..value.len() - 1] } else { value };
+    match value {
+        "t" | "T" | "true" | "True" | "TRUE" => Ok(Some("1".to_string())),
+        "f" | "F" | "false" | "False" | "FALSE" => Ok(Some("0".to_string())),
+        _ if number.parse::<f64>().is_ok() => Ok(Some(number.to_string())),
+        _ => Err(format!("invalid value '{}'", value)),
+    }
+}
+
+/// Splits at separators that are neither escaped by `\` nor within double quotes.
+fn split_unescaped(s: &str, separator: char) -> Vec<String> {
+    let mut parts = Vec::new();
+    let mut part = String::new();
+    let mut chars = s.chars();
+    let mut quoted = false;
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                part.push(c);
+                if let Some(escaped) = chars.next() {
+                    part.push(escaped);
+                }
+            }
+            '"' => {
+                quoted = !quoted;
+                part.push(c);
+            }
+            _ if c == separator && !quoted => {
+                if separator != ' ' || !part.is_empty() {
+                    parts.push(part);
+                }
+                part = String::new();
+            }
+            _ => part.push(c),
+        }
+    }
+    if !part.is_empty() || separator != ' ' {
+        parts.push(part);
+    }
+
+    parts
+}
+
+fn split_key_value(s: &str) -> Option<(String, String)> {
+    let parts = split_unescaped(s, '=');
+    match parts.len() {
+        2 if !parts[0].is_empty() && !parts[1].is_empty() => Some((unescape(&parts[0]), unescape(&parts[1]))),
+        _ => None,
+    }
+}
+
+fn unescape(s: &str) -> String {
+    let mut unescaped = String::new();
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => unescaped.extend(chars.next()),
+            _ => unescaped.push(c),
+        }
+    }
+
+    unescaped
+}
@@ -0,0 +1,79 @@
+//! Reads metric data from line-oriented text formats.
+
+use std::str::FromStr;
+
+use super::{DataPoint, influx, opentsdb};
+use super::graphite::Templates;
+use super::influx::Precision;
+
+/// Supported input formats
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    /// OpenTSDB's line format, cf. `opentsdb::parse_line`
+    OpenTsdb,
+    /// One JSON object per line as accepted by Bosun's `/api/put`, cf. `DataPoint::from_json`
+    Json,
+    /// Graphite's plaintext protocol with paths as metric names, cf. `graphite::Templates::parse_line`
+    Graphite,
+    /// InfluxDB's line protocol with timestamps of the precision given, cf. `influx::parse_line`
+    Influx(Precision),
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Format, String> {
+        match s {
+            "opentsdb" => Ok(Format::OpenTsdb),
+            "json" => Ok(Format::Json),
+            "graphite" => Ok(Format::Graphite),
+            "influx" => Ok(Format::Influx(Precision::Nanoseconds)),
+            _ => Err(format!("unknown input format '{}'", s)),
+        }
+    }
+}
+
+/// Parses all data points from one line of input; empty lines yield no data points.
+///
+/// # Example
+///
+/// ```
+/// # use bosun_emitter::input::{self, Format};
+/// let data = input::parse_line(Format::Json, r#"{"metric":"backup.runtime","timestamp":1458066838,"value":42}"#).unwrap();
+///
+/// assert_eq!(data.len(), 1);
+/// assert_eq!(data[0].metric, "backup.runtime");
+/// ```
+pub fn parse_line(format: Format, line: &str) -> Result<Vec<DataPoint>, String> {
+    match format {
+        Format::OpenTsdb => opentsdb::parse_line(line).map(|data_point| data_point.into_iter().collect()),
+        Format::Json if line.trim().is_empty() => Ok(Vec::new()),
+        Format::Json => DataPoint::from_json(line).map(|data_point| vec![data_point]),
+        Format::Graphite => Templates::default().parse_line(line).map(|data_point| data_point.into_iter().collect()),
+        Format::Influx(precision) => influx::parse_line(line, precision),
+    }
+}
+
+/// Guesses the format of a line, so clients may mix formats: JSON objects, InfluxDB's line protocol,
+/// whose second field contains `=`, and OpenTSDB's line format otherwise.
+///
+/// # Example
+///
+/// ```
+/// # use bosun_emitter::input::{self, Format};
+/// # use bosun_emitter::influx::Precision;
+/// assert_eq!(input::detect_format(r#"{"metric":"backup.runtime"}"#), Format::Json);
+/// assert_eq!(input::detect_format("backup,job=daily runtime=42 1458066838000000000"), Format::Influx(Precision::Nanoseconds));
+/// assert_eq!(input::detect_format("put backup.runtime 1458066838 42 job=daily"), Format::OpenTsdb);
+/// ```
+pub fn detect_format(line: &str) -> Format {
+    let line = line.trim_start();
+    let mut fields = line.split_whitespace();
+    if line.starts_with('{') {
+        Format::Json
+    } else if fields.next() != Some("put") && fields.next().map_or(false, |field| field.contains('=')) {
+        Format::Influx(Precision::Nanoseconds)
+    } else {
+        Format::OpenTsdb
+    }
+}
@@ -0,0 +1,189 @@
+//! OpenTSDB's line format as used by its telnet interface and by scollector's external collectors.
+//!
+//! Each line consists of `[put] <metric> <timestamp> <value> [<tagk1=tagv1> ...]`.
+//!
+//! `serve_telnet` speaks OpenTSDB's telnet protocol, so collectors that send to OpenTSDB can send to
+//! a local agent instead, cf. `agent::Agent::listen_telnet`.
+
+use std::io::{self, BufRead, Write};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::Sender;
+
+use super::{DataPoint, Datum, Tags, now_in_ms};
+
+/// Parses a data point from a line; empty lines and comments starting with `#` yield `None`.
+///
+/// # Example
+///
+/// ```
+/// # use bosun_emitter::opentsdb;
+/// let data_point = opentsdb::parse_line("backup.runtime 1458066838 42 type=mongodb").unwrap().unwrap();
+///
+/// assert_eq!(data_point.metric, "backup.runtime");
+/// assert_eq!(data_point.timestamp, 1458066838);
+/// assert_eq!(data_point.value, "42");
+/// assert_eq!(data_point.tags["type"], "mongodb");
+/// ```
+pub fn parse_line(line: &str) -> Result<Option<DataPoint>, String> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+        return Ok(None);
+    }
+
+    let mut fields = line.split_whitespace().peekable();
+    if fields.peek() == Some(&"put") {
+        let _ = fields.next();
+    }
+    let metric = try!(fields.next().ok_or("missing metric"));
+    let timestamp = try!(fields.next().ok_or("missing timestamp"));
+    let timestamp = try!(timestamp.parse::<i64>().map_err(|_| format!("invalid timestamp '{}'", timestamp)));
+    let value = try!(fields.next().ok_or("missing value"));
+    let mut tags = Tags::new();
+    for tag in fields {
+        let mut kv = tag.splitn(2, '=');
+        match (kv.next(), kv.next()) {
+            (Some(key), Some(value)) => {
+                let _ = tags.insert(key.to_string(), value.to_string());
+            }
+            _ => return Err(format!("invalid tag '{}'", tag)),
+        }
+    }
+
+    let data_point = DataPoint::new(metric, timestamp, value, tags);
+    try!(data_point.validate());
+
+    Ok(Some(data_point))
+}
+
+/// Formats a data point as line with its tags sorted.
+///
+/// # Example
+///
+/// ```
+/// # use bosun_emitter::{DataPoint, Tags};
+/// # use bosun_emitter::opentsdb;
+/// let mut tags = Tags::new();
+/// tags.insert("type".to_string(), "mongodb".to_string());
+/// tags.insert("host".to_string(), "webserver".to_string());
+/// let data_point = DataPoint::new("backup.runtime", 1458066838, "42", tags);
+///
+/// assert_eq!(opentsdb::format_line(&data_point), "backup.runtime 1458066838 42 host=webserver type=mongodb");
+/// ```
+pub fn format_line(data_point: &DataPoint) -> String {
+    format_datum(&data_point.as_datum())
+}
+
+/// Formats a datum as line with its tags sorted, e.g., for scollector's external collectors.
+pub fn format_datum(datum: &Datum) -> String {
+    let mut tags: Vec<String> = datum.tags.iter().map(|(key, value)| format!("{}={}", key, value)).collect();
+    tags.sort();
+    let mut line = format!("{} {} {}", datum.metric, datum.timestamp, datum.value);
+    for tag in tags {
+        line.push(' ');
+        line.push_str(&tag);
+    }
+
+    line
+}
+
+/// Statistics of a telnet listener as reported by the `stats` command.
+#[derive(Debug, Default)]
+pub struct TelnetStats {
+    connections: AtomicUsize,
+    received: AtomicUsize,
+    errors: AtomicUsize,
+}
+
+impl TelnetStats {
+    /// Creates empty statistics.
+    pub fn new() -> TelnetStats {
+        TelnetStats::default()
+    }
+
+    /// Returns the statistics as data points named like OpenTSDB's.
+    pub fn data_points(&self, timestamp: i64) -> Vec<DataPoint> {
+        let stat = |metric: &str, value: &AtomicUsize, tag: Option<(&str, &str)>| {
+            let mut tags = Tags::new();
+            if let Some((key, value)) = tag {
+                tags.insert(key.to_string(), value.to_string());
+            }
+            DataPoint::new(metric, timestamp, value.load(Ordering::SeqCst).to_string(), tags)
+        };
+
+        vec![stat("tsd.connectionmgr.connections", &self.connections, None),
+             stat("tsd.rpc.received", &self.received, Some(("type", "put"))),
+             stat("tsd.rpc.errors", &self.errors, Some(("type", "invalid_values")))]
+    }
+}
+
+/// Serves one connection speaking OpenTSDB's telnet protocol and passes the data points received on.
+///
+/// Like OpenTSDB, only errors of `put` are answered; `version` and `stats` print the library's
+/// version and the listener's statistics, `help` lists the commands, and `exit` closes the connection.
+///
+/// # Example
+///
+/// ```
+/// # use std::sync::mpsc;
+/// # use bosun_emitter::opentsdb::{self, TelnetStats};
+/// let (sender, receiver) = mpsc::channel();
+/// let mut reply = Vec::new();
+/// let input = "put backup.runtime 1458066838 42 type=mongodb\nput backup.size 1458066838\nexit\n";
+///
+/// opentsdb::serve_telnet(input.as_bytes(), &mut reply, &sender, &TelnetStats::new()).unwrap();
+///
+/// assert_eq!(String::from_utf8(reply).unwrap(), "put: illegal argument: missing value\n");
+/// assert_eq!(receiver.recv().unwrap().metric, "backup.runtime");
+/// ```
+pub fn serve_telnet<R: BufRead, W: Write>(reader: R,
+                                          mut writer: W,
+                                          sender: &Sender<DataPoint>,
+                                          stats: &TelnetStats)
+                                          -> io::Result<()> {
+    stats.connections.fetch_add(1, Ordering::SeqCst);
+    let result = serve_commands(reader, &mut writer, sender, stats);
+    stats.connections.fetch_sub(1, Ordering::SeqCst);
+
+    result
+}
+
+fn serve_commands<R: BufRead, W: Write>(reader: R,
+                                        writer: &mut W,
+                                        sender: &Sender<DataPoint>,
+                                        stats: &TelnetStats)
+                                        -> io::Result<()> {
+    for line in reader.lines() {
+        let line = try!(line);
+        let command = line.split_whitespace().next().unwrap_or("");
+        match command {
+            "" => {}
+            "put" => {
+                stats.received.fetch_add(1, Ordering::SeqCst);
+                match parse_line(&line) {
+                    Ok(Some(data_point)) => {
+                        if sender.send(data_point).is_err() {
+                            return Err(io::Error::new(io::ErrorKind::BrokenPipe, "agent stopped"));
+                        }
+                    }
+                    Ok(None) => {}
+                    Err(e) => {
+                        stats.errors.fetch_add(1, Ordering::SeqCst);
+                        try!(writeln!(writer, "put: illegal argument: {}", e));
+                    }
+                }
+            }
+            "version" => try!(writeln!(writer, "bosun_emitter version {}", env!("CARGO_PKG_VERSION"))),
+            "stats" => {
+                for data_point in stats.data_points(now_in_ms() / 1000) {
+                    try!(writeln!(writer, "{}", format_line(&data_point)));
+                }
+            }
+            "help" => try!(writeln!(writer, "available commands: put stats version help exit")),
+            "exit" => break,
+            _ => try!(writeln!(writer, "unknown command: {}.  Try `help'.", command)),
+        }
+        try!(writer.flush());
+    }
+
+    Ok(())
+}
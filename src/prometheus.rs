@@ -0,0 +1,367 @@
+//! Prometheus' text exposition format as served by `/metrics` endpoints, converted to data points
+//! with meta data.
+//!
+//! Samples keep their metric names; labels become tags according to `LabelRules`. `# HELP` becomes
+//! the description and `# TYPE` the rate: counters as well as the buckets, sums, and counts of
+//! histograms and summaries are counters, all others gauges. The unit is taken from the metric
+//! name's suffix like `_seconds` or `_bytes`. Samples with values Bosun cannot store, i.e., `NaN` and
+//! infinities, are skipped.
+//!
+//! # Example
+//!
+//! ```
+//! # use bosun_emitter::prometheus::{self, LabelRules};
+//! let text = concat!("# HELP http_requests_total Requests handled\n",
+//!                    "# TYPE http_requests_total counter\n",
+//!                    "http_requests_total{method=\"GET\",instance=\"web01:9100\"} 1027 1458066838000\n");
+//! let rules = LabelRules::parse(&["instance=host"]).unwrap();
+//! let (samples, errors) = prometheus::parse(text, 1458066900000, &rules);
+//!
+//! assert!(errors.is_empty());
+//! assert_eq!(samples[0].data_point.metric, "http_requests_total");
+//! assert_eq!(samples[0].data_point.value, "1027");
+//! assert_eq!(samples[0].data_point.timestamp, 1458066838000);
+//! assert_eq!(samples[0].data_point.tags["host"], "web01_9100");
+//! assert_eq!(samples[0].rate, "counter");
+//! assert_eq!(samples[0].description, "Requests handled");
+//! ```
+
+use std::collections::HashMap;
+use std::io::Read;
+use std::time::Duration;
+
+use reqwest;
+
+use super::{DataPoint, EmitterError, Metadata, Tags, is_valid_name};
+
+/// Units recognized as metric name suffix
+static UNITS: &'static [&'static str] = &["seconds", "bytes", "ratio", "percent", "celsius", "meters", "volts",
+                                         "amperes", "joules", "grams"];
+
+/// Type of a metric family
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Kind {
+    /// Monotonically increasing value
+    Counter,
+    /// Value that goes up and down
+    Gauge,
+    /// Observations counted in buckets
+    Histogram,
+    /// Observations summarized in quantiles
+    Summary,
+    /// Untyped value, treated as gauge
+    Untyped,
+}
+
+/// A sample converted to a data point together with its meta data.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sample {
+    /// Converted data point
+    pub data_point: DataPoint,
+    /// Metric rate type: [gauge, counter rate]
+    pub rate: &'static str,
+    /// Metric unit
+    pub unit: String,
+    /// Metric description
+    pub description: String,
+}
+
+impl Sample {
+    /// Returns the meta data of the sample's metric.
+    pub fn metadata<'a>(&'a self) -> Metadata<'a> {
+        Metadata::new(&self.data_point.metric, self.rate, &self.unit, &self.description)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum LabelRule {
+    Rename(String, String),
+    Drop(String),
+}
+
+/// Rules mapping labels to tags; labels without rule become tags of the same name.
+///
+/// A rule `<label>=<tag>` renames a label, `-<label>` drops it. Characters Bosun does not allow are
+/// replaced by `_`, and empty labels are dropped.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LabelRules {
+    rules: Vec<LabelRule>,
+}
+
+impl LabelRules {
+    /// Parses rules like `instance=host` or `-job`.
+    pub fn parse<S: AsRef<str>>(rules: &[S]) -> Result<LabelRules, String> {
+        let mut parsed = Vec::new();
+        for rule in rules {
+            let rule = rule.as_ref().trim();
+            let mut kv = rule.splitn(2, '=');
+            let parsed_rule = match (kv.next(), kv.next()) {
+                (Some(label), None) if rule.starts_with('-') && rule.len() > 1 => LabelRule::Drop(label[1..].to_string()),
+                (Some(label), Some(tag)) if !label.is_empty() && is_valid_name(tag) => {
+                    LabelRule::Rename(label.to_string(), tag.to_string())
+                }
+                _ => return Err(format!("label rule '{}' is neither <LABEL>=<TAG> nor -<LABEL>", rule)),
+            };
+            parsed.push(parsed_rule);
+        }
+
+        Ok(LabelRules { rules: parsed })
+    }
+
+    /// Converts labels to tags.
+    pub fn apply(&self, labels: &[(String, String)]) -> Tags {
+        let mut tags = Tags::new();
+        for (label, value) in labels {
+            let mut key = Some(label.as_str());
+            for rule in &self.rules {
+                match *rule {
+                    LabelRule::Rename(ref from, ref to) if from == label => key = Some(to),
+                    LabelRule::Drop(ref dropped) if dropped == label => key = None,
+                    _ => {}
+                }
+            }
+            if let Some(key) = key {
+                if !value.is_empty() {
+                    tags.insert(sanitize(key), sanitize(value));
+                }
+            }
+        }
+
+        tags
+    }
+}
+
+/// Replaces characters Bosun does not allow in names by `_`; `+Inf` bucket bounds become `inf`.
+fn sanitize(name: &str) -> String {
+    if name == "+Inf" {
+        return "inf".to_string();
+    }
+    name.chars()
+        .map(|c| if c.is_alphanumeric() || c == '-' || c == '_' || c == '.' || c == '/' { c } else { '_' })
+        .collect()
+}
+
+#[derive(Debug, Default)]
+struct Family {
+    kind: Option<Kind>,
+    help: Option<String>,
+}
+
+/// Parses an exposition and returns its samples as well as the malformed lines.
+///
+/// Samples without timestamp get `timestamp`, i.e., the time of the scrape in ms.
+pub fn parse(text: &str, timestamp: i64, rules: &LabelRules) -> (Vec<Sample>, Vec<String>) {
+    let mut families: HashMap<String, Family> = HashMap::new();
+    let mut samples = Vec::new();
+    let mut errors = Vec::new();
+    for (index, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        if line.starts_with('#') {
+            let mut fields = line[1..].trim_start().splitn(3, ' ');
+            match (fields.next(), fields.next(), fields.next()) {
+                (Some("HELP"), Some(name), help) => {
+                    families.entry(name.to_string()).or_default().help = Some(unescape(help.unwrap_or("").trim()));
+                }
+                (Some("TYPE"), Some(name), Some(kind)) => {
+                    let kind = match kind.trim() {
+                        "counter" => Kind::Counter,
+                        "gauge" => Kind::Gauge,
+                        "histogram" => Kind::Histogram,
+                        "summary" => Kind::Summary,
+                        "untyped" => Kind::Untyped,
+                        other => {
+                            errors.push(format!("line {}: unknown type '{}'", index + 1, other));
+                            continue;
+                        }
+                    };
+                    families.entry(name.to_string()).or_default().kind = Some(kind);
+                }
+                // Other comments
+                _ => {}
+            }
+            continue;
+        }
+
+        match parse_sample(line, timestamp) {
+            Ok(Some((name, labels, data_point))) => {
+                let (family, suffix) = family_of(&families, &name);
+                let (kind, help) = match families.get(family) {
+                    Some(f) => (f.kind.unwrap_or(Kind::Untyped), f.help.clone().unwrap_or_default()),
+                    None => (Kind::Untyped, String::new()),
+                };
+                let (rate, unit, description) = metadata_of(family, suffix, kind, help);
+                let mut data_point = data_point;
+                data_point.metric = sanitize(&name);
+                data_point.tags = rules.apply(&labels);
+                match data_point.validate() {
+                    Ok(_) => {
+                        samples.push(Sample {
+                            data_point: data_point,
+                            rate: rate,
+                            unit: unit,
+                            description: description,
+                        })
+                    }
+                    Err(e) => errors.push(format!("line {}: {}", index + 1, e)),
+                }
+            }
+            Ok(None) => {}
+            Err(e) => errors.push(format!("line {}: {}", index + 1, e)),
+        }
+    }
+
+    (samples, errors)
+}
+
+/// Returns the family a sample belongs to and the sample's suffix, e.g., `_bucket` of histograms.
+fn family_of<'a>(families: &HashMap<String, Family>, name: &'a str) -> (&'a str, &'a str) {
+    for suffix in &["_bucket", "_sum", "_count"] {
+        if name.ends_with(suffix) {
+            let family = &name[..name.len() - suffix.len()];
+            match families.get(family).and_then(|f| f.kind) {
+                Some(Kind::Histogram) | Some(Kind::Summary) => return (family, suffix),
+                _ => {}
+            }
+        }
+    }
+
+    (name, "")
+}
+
+fn metadata_of(family: &str, suffix: &str, kind: Kind, help: String) -> (&'static str, String, String) {
+    let base_unit = family.trim_end_matches("_total")
+        .rsplit('_')
+        .next()
+        .and_then(|unit| UNITS.iter().find(|u| **u == unit))
+        .map(|unit| unit.to_string());
+    let counted = || "count".to_string();
+    match (kind, suffix) {
+        (Kind::Histogram, "_bucket") => ("counter", counted(), describe(help, "cumulative count of observations per upper bound le")),
+        (Kind::Histogram, "_count") | (Kind::Summary, "_count") => ("counter", counted(), describe(help, "count of observations")),
+        (Kind::Histogram, "_sum") | (Kind::Summary, "_sum") => {
+            ("counter", base_unit.unwrap_or_else(|| "value".to_string()), describe(help, "sum of observations"))
+        }
+        (Kind::Counter, _) => ("counter", base_unit.unwrap_or_else(counted), help),
+        _ => ("gauge", base_unit.unwrap_or_else(|| "value".to_string()), help),
+    }
+}
+
+fn describe(help: String, what: &str) -> String {
+    if help.is_empty() { what.to_string() } else { format!("{}; {}", help, what) }
+}
+
+type Labels = Vec<(String, String)>;
+type ParsedSample = (String, Labels, DataPoint);
+
+/// Parses `<name>[{<label>="<value>",...}] <value> [<timestamp in ms>]`; returns `None` for samples
+/// Bosun cannot store.
+fn parse_sample(line: &str, timestamp: i64) -> Result<Option<ParsedSample>, String> {
+    let name_end = line.find(|c: char| c == '{' || c.is_whitespace()).unwrap_or(line.len());
+    let name = &line[..name_end];
+    if name.is_empty() {
+        return Err("missing metric name".to_string());
+    }
+    let mut rest = &line[name_end..];
+    let mut labels = Vec::new();
+    if rest.starts_with('{') {
+        let (parsed, remaining) = try!(parse_labels(&rest[1..]));
+        labels = parsed;
+        rest = remaining;
+    }
+    let mut fields = rest.split_whitespace();
+    let value = try!(fields.next().ok_or("missing value"));
+    let value = try!(value.parse::<f64>().map_err(|_| format!("invalid value '{}'", value)));
+    let timestamp = match fields.next() {
+        Some(t) => try!(t.parse::<i64>().map_err(|_| format!("invalid timestamp '{}'", t))),
+        None => timestamp,
+    };
+    if !value.is_finite() {
+        return Ok(None);
+    }
+
+    Ok(Some((name.to_string(), labels, DataPoint::new(name, timestamp, value.to_string(), Tags::new()))))
+}
+
+/// Parses labels up to the closing `}` and returns them with the rest of the line.
+fn parse_labels(s: &str) -> Result<(Labels, &str), String> {
+    let mut labels = Vec::new();
+    let mut rest = s.trim_start();
+    loop {
+        if rest.starts_with('}') {
+            return Ok((labels, &rest[1..]));
+        }
+        let eq = try!(rest.find('=').ok_or("unterminated labels"));
+        let label = rest[..eq].trim().to_string();
+        rest = rest[eq + 1..].trim_start();
+        if !rest.starts_with('"') {
+            return Err(format!("unquoted value of label '{}'", label));
+        }
+        let mut value = String::new();
+        let mut chars = rest[1..].char_indices();
+        let mut end = None;
+        while let Some((i, c)) = chars.next() {
+            match c {
+                '\\' => {
+                    match chars.next() {
+                        Some((_, 'n')) => value.push('\n'),
+                        Some((_, escaped)) => value.push(escaped),
+                        None => break,
+                    }
+                }
+                '"' => {
+                    end = Some(i + 2);
+                    break;
+                }
+                _ => value.push(c),
+            }
+        }
+        let end = try!(end.ok_or_else(|| format!("unterminated value of label '{}'", label)));
+        labels.push((label, value));
+        rest = rest[end..].trim_start();
+        if rest.starts_with(',') {
+            rest = rest[1..].trim_start();
+        }
+    }
+}
+
+fn unescape(s: &str) -> String {
+    let mut unescaped = String::new();
+    let mut chars = s.chars().peekable();
+    while let Some(c) = chars.next() {
+        match chars.peek() {
+            Some(&'n') if c == '\\' => {
+                let _ = chars.next();
+                unescaped.push('\n');
+            }
+            Some(&'\\') if c == '\\' => {
+                let _ = chars.next();
+                unescaped.push('\\');
+            }
+            _ => unescaped.push(c),
+        }
+    }
+
+    unescaped
+}
+
+/// Fetches an exposition from an HTTP(S) endpoint.
+pub fn fetch(url: &str, timeout: u64) -> Result<String, EmitterError> {
+    let client = try!(reqwest::Client::builder()
+        .timeout(Duration::from_secs(timeout))
+        .build()
+        .map_err(|e| EmitterError::EmitError(format!("failed to build http client because {}", e))));
+    let mut response = try!(client.get(url)
+        .header("Accept", "text/plain; version=0.0.4")
+        .send()
+        .map_err(|e| EmitterError::EmitError(format!("{}", e))));
+    if !response.status().is_success() {
+        return Err(EmitterError::ReceiveError(format!("{}", response.status())));
+    }
+    let mut text = String::new();
+    try!(response.read_to_string(&mut text));
+
+    Ok(text)
+}
@@ -0,0 +1,123 @@
+//! Spools requests which could not be sent to Bosun in a directory in order to resend them later.
+//!
+//! # Example
+//!
+//! ```no_run
+//! use bosun_emitter::{BosunClient, Datum, EmitterError, Tags};
+//! use bosun_emitter::spool::Spool;
+//!
+//! let client = BosunClient::new("localhost:8070", 5);
+//! let spool = Spool::new("/var/spool/emit_bosun");
+//! let _ = spool.flush(&client);
+//!
+//! let tags = Tags::new();
+//! let datum = Datum::now("lukas.tests.count", "42", &tags);
+//! if let Err(EmitterError::EmitError(_)) = client.emit_datum(&datum) {
+//!     let _ = spool.store_datum(&datum);
+//! }
+//! ```
+
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::PathBuf;
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use super::{BosunClient, Datum, EmitterError, Metadata, DATUM_API_PATH, METADATA_API_PATH, now_in_ms};
+
+static SEQUENCE: AtomicUsize = AtomicUsize::new(0);
+static SPOOL_FILE_EXTENSION: &'static str = "spool";
+
+/// A directory of requests waiting to be sent to Bosun.
+#[derive(Debug)]
+pub struct Spool {
+    dir: PathBuf,
+}
+
+impl Spool {
+    /// Creates a new Spool; the directory is created when the first request is stored.
+    pub fn new<P: Into<PathBuf>>(dir: P) -> Spool {
+        Spool { dir: dir.into() }
+    }
+
+    /// Stores a metric datum.
+    pub fn store_datum(&self, datum: &Datum) -> Result<PathBuf, EmitterError> {
+        let encoded = try!(datum.to_json());
+        self.store(DATUM_API_PATH, &encoded)
+    }
+
+    /// Stores metric meta data.
+    pub fn store_metadata(&self, metadata: &Metadata) -> Result<PathBuf, EmitterError> {
+        let encoded = try!(metadata.to_json());
+        self.store(METADATA_API_PATH, &encoded)
+    }
+
+    /// Stores a JSON document for a Bosun API path; the spool file is written atomically.
+    pub fn store(&self, path: &str, json: &str) -> Result<PathBuf, EmitterError> {
+        try!(fs::create_dir_all(&self.dir));
+        let file_name = format!("{:013}-{:010}-{:010}.{}",
+                                now_in_ms(),
+                                process::id(),
+                                SEQUENCE.fetch_add(1, Ordering::SeqCst),
+                                SPOOL_FILE_EXTENSION);
+        let tmp_file_path = self.dir.join(format!(".{}", file_name));
+        let file_path = self.dir.join(file_name);
+        {
+            let mut file = try!(File::create(&tmp_file_path));
+            try!(write!(file, "{}\n{}", path, json));
+            try!(file.sync_all());
+        }
+        try!(fs::rename(&tmp_file_path, &file_path));
+        debug!("Spooled request for '{}' to '{}'.", path, file_path.display());
+
+        Ok(file_path)
+    }
+
+    /// Returns the spooled requests in the order they have been stored.
+    pub fn pending(&self) -> io::Result<Vec<PathBuf>> {
+        if !self.dir.exists() {
+            return Ok(Vec::new());
+        }
+        let mut pending = Vec::new();
+        for entry in try!(fs::read_dir(&self.dir)) {
+            let path = try!(entry).path();
+            let is_spool_file = path.extension().map(|e| e == SPOOL_FILE_EXTENSION).unwrap_or(false);
+            let is_hidden = path.file_name().and_then(|n| n.to_str()).map(|n| n.starts_with('.')).unwrap_or(true);
+            if is_spool_file && !is_hidden {
+                pending.push(path);
+            }
+        }
+        pending.sort();
+
+        Ok(pending)
+    }
+
+    /// Resends all spooled requests and returns how many have been sent.
+    ///
+    /// Sent requests as well as requests rejected by Bosun are removed. Flushing stops at the first
+    /// request that cannot be sent, so the remaining requests keep their order. In dry-run mode, all
+    /// requests are kept.
+    pub fn flush(&self, client: &BosunClient) -> Result<usize, EmitterError> {
+        let mut sent = 0;
+        for file_path in try!(self.pending()) {
+            let mut content = String::new();
+            try!(File::open(&file_path).and_then(|mut f| f.read_to_string(&mut content)));
+            let mut parts = content.splitn(2, '\n');
+            let path = parts.next().unwrap_or("");
+            let json = parts.next().unwrap_or("");
+            match client.send(path, json) {
+                Ok(_) => sent += 1,
+                Err(EmitterError::ReceiveError(e)) => {
+                    warn!("Dropping spooled request '{}', because Bosun rejected it with {}.", file_path.display(), e);
+                }
+                Err(err) => return Err(err),
+            }
+            // A dry run only renders the requests, so they must still be sent later
+            if client.dry_run.is_none() {
+                try!(fs::remove_file(&file_path));
+            }
+        }
+
+        Ok(sent)
+    }
+}
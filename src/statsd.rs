@@ -0,0 +1,388 @@
+//! Receives StatsD metrics via UDP and aggregates them per flush interval.
+//!
+//! Each line of a packet is one sample `<metric>:<value>|<type>[|@<sample rate>][|#<tag>:<value>,...]`,
+//! i.e., StatsD's format with DogStatsD's tags. Types are counters `c`, timers `ms`, histograms `h`,
+//! gauges `g`, where a leading `+` or `-` changes the previous value, and sets `s`.
+//!
+//! Each flush yields the following data points for all metrics received within the flush interval:
+//!
+//! * counters: `<metric>.count` per interval and `<metric>.rate` per second
+//! * timers and histograms: `<metric>.count`, `.min`, `.max`, `.mean`, and a percentile like `.p90`
+//!   for each percentile configured
+//! * gauges: `<metric>`
+//! * sets: `<metric>.count` of unique values
+//!
+//! # Example
+//!
+//! ```no_run
+//! use std::thread;
+//! use std::time::Duration;
+//! use bosun_emitter::{BosunClient, Datum, now_in_ms};
+//! use bosun_emitter::statsd::Listener;
+//!
+//! let client = BosunClient::new("localhost:8070", 5);
+//! let listener = Listener::bind("127.0.0.1:8125", vec![90.0, 99.0]).unwrap();
+//! loop {
+//!     thread::sleep(Duration::from_secs(10));
+//!     let aggregates = listener.flush(now_in_ms(), Duration::from_secs(10));
+//!     let metadata: Vec<_> = aggregates.iter().map(|aggregate| aggregate.metadata()).collect();
+//!     let _ = client.emit_metadata_list(&metadata);
+//!     let data: Vec<Datum> = aggregates.iter().map(|aggregate| aggregate.data_point.as_datum()).collect();
+//!     let _ = client.emit_data(&data);
+//! }
+//! ```
+
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use super::{DataPoint, Metadata, Tags, is_valid_name};
+
+/// Default address to receive StatsD metrics on
+pub static DEFAULT_STATSD_ADDRESS: &'static str = "127.0.0.1:8125";
+/// Percentiles of timers sent by default
+pub static DEFAULT_PERCENTILES: &'static [f64] = &[90.0, 95.0, 99.0];
+
+/// Value of a StatsD sample
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    /// Counter increment, `c`
+    Counter(f64),
+    /// Timing in ms, `ms`
+    Timer(f64),
+    /// Value of a histogram, `h`
+    Histogram(f64),
+    /// Gauge value, `g`
+    Gauge(f64),
+    /// Change of a gauge, i.e., `g` with a leading `+` or `-`
+    GaugeDelta(f64),
+    /// Member of a set, `s`
+    Set(String),
+}
+
+/// A sample as received from a StatsD client.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sample {
+    /// Metric name
+    pub metric: String,
+    /// Sample value
+    pub value: Value,
+    /// Fraction of samples the client sends, e.g., 0.1 for every tenth sample
+    pub sample_rate: f64,
+    /// DogStatsD tags; tags without value get the value `true`
+    pub tags: Tags,
+}
+
+/// Parses all samples of a packet and returns them as well as the malformed lines.
+///
+/// # Example
+///
+/// ```
+/// # use bosun_emitter::statsd::{self, Value};
+/// let (samples, errors) = statsd::parse_packet("logins:1|c|@0.5|#env:prod\nlogin.time:320|ms\nlogins");
+///
+/// assert_eq!(samples[0].metric, "logins");
+/// assert_eq!(samples[0].value, Value::Counter(1.0));
+/// assert_eq!(samples[0].sample_rate, 0.5);
+/// assert_eq!(samples[0].tags["env"], "prod");
+/// assert_eq!(samples[1].value, Value::Timer(320.0));
+/// assert_eq!(errors, vec!["sample 'logins' is not <METRIC>:<VALUE>|<TYPE>".to_string()]);
+/// ```
+pub fn parse_packet(packet: &str) -> (Vec<Sample>, Vec<String>) {
+    let mut samples = Vec::new();
+    let mut errors = Vec::new();
+    for line in packet.lines().map(|l| l.trim()).filter(|l| !l.is_empty()) {
+        match parse_sample(line) {
+            Ok(sample) => samples.push(sample),
+            Err(e) => errors.push(e),
+        }
+    }
+
+    (samples, errors)
+}
+
+/// Parses one sample, e.g., `logins:1|c|@0.5|#env:prod`.
+pub fn parse_sample(line: &str) -> Result<Sample, String> {
+    let malformed = || format!("sample '{}' is not <METRIC>:<VALUE>|<TYPE>", line);
+    let mut parts = line.splitn(2, ':');
+    let (metric, rest) = match (parts.next(), parts.next()) {
+        (Some(metric), Some(rest)) if !metric.is_empty() => (metric, rest),
+        _ => return Err(malformed()),
+    };
+    if !is_valid_name(metric) {
+        return Err(format!("invalid metric name '{}'", metric));
+    }
+    let mut fields = rest.split('|');
+    let (value, kind) = match (fields.next(), fields.next()) {
+        (Some(value), Some(kind)) if !value.is_empty() => (value, kind),
+        _ => return Err(malformed()),
+    };
+    let number = || -> Result<f64, String> {
+        value.parse::<f64>()
+            .ok()
+            .filter(|v| v.is_finite())
+            .ok_or_else(|| format!("invalid value '{}' of '{}'", value, metric))
+    };
+    let value = match kind {
+        "c" => Value::Counter(try!(number())),
+        "ms" => Value::Timer(try!(number())),
+        "h" => Value::Histogram(try!(number())),
+        "g" if value.starts_with('+') || value.starts_with('-') => Value::GaugeDelta(try!(number())),
+        "g" => Value::Gauge(try!(number())),
+        "s" => Value::Set(value.to_string()),
+        _ => return Err(format!("unknown type '{}' of '{}'", kind, metric)),
+    };
+
+    let mut sample_rate = 1.0;
+    let mut tags = Tags::new();
+    for field in fields {
+        if field.starts_with('@') {
+            let rate = &field[1..];
+            sample_rate = try!(rate.parse::<f64>()
+                .ok()
+                .filter(|&rate| rate > 0.0 && rate <= 1.0)
+                .ok_or_else(|| format!("invalid sample rate '{}' of '{}'", rate, metric)));
+        } else if field.starts_with('#') {
+            for tag in field[1..].split(',').filter(|t| !t.is_empty()) {
+                let mut kv = tag.splitn(2, ':');
+                let (key, value) = (kv.next().unwrap_or(""), kv.next().unwrap_or("true"));
+                if !is_valid_name(key) || !is_valid_name(value) {
+                    return Err(format!("invalid tag '{}' of '{}'", tag, metric));
+                }
+                tags.insert(key.to_string(), value.to_string());
+            }
+        }
+        // Other extensions like DogStatsD's container ids do not apply to Bosun
+    }
+
+    Ok(Sample {
+        metric: metric.to_string(),
+        value: value,
+        sample_rate: sample_rate,
+        tags: tags,
+    })
+}
+
+/// A data point aggregated from samples together with its meta data.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Aggregate {
+    /// Aggregated data point
+    pub data_point: DataPoint,
+    /// Metric rate type: [gauge, counter rate]
+    pub rate: &'static str,
+    /// Metric unit
+    pub unit: &'static str,
+    /// Metric description
+    pub description: String,
+}
+
+impl Aggregate {
+    fn new(data_point: DataPoint, rate: &'static str, unit: &'static str, description: String) -> Aggregate {
+        Aggregate {
+            data_point: data_point,
+            rate: rate,
+            unit: unit,
+            description: description,
+        }
+    }
+
+    /// Returns the meta data of the aggregate's metric.
+    pub fn metadata<'a>(&'a self) -> Metadata<'a> {
+        Metadata::new(&self.data_point.metric, self.rate, self.unit, &self.description)
+    }
+}
+
+/// Metric name and sorted tags
+type Key = (String, Vec<(String, String)>);
+
+/// Values of a timer or histogram within a flush interval
+#[derive(Debug, Default)]
+struct Timings {
+    values: Vec<f64>,
+    /// Number of samples corrected by the sample rate
+    count: f64,
+    unit: &'static str,
+}
+
+/// Aggregates samples per metric and tags until they are flushed.
+#[derive(Debug, Default)]
+pub struct Aggregator {
+    percentiles: Vec<f64>,
+    counters: HashMap<Key, f64>,
+    timings: HashMap<Key, Timings>,
+    /// Gauges keep their value across flushes for changes; the flag marks gauges set since the last flush
+    gauges: HashMap<Key, (f64, bool)>,
+    sets: HashMap<Key, HashSet<String>>,
+}
+
+impl Aggregator {
+    /// Creates an aggregator computing the given percentiles of timers, e.g., `90.0`.
+    pub fn new(percentiles: Vec<f64>) -> Aggregator {
+        Aggregator { percentiles: percentiles, ..Aggregator::default() }
+    }
+
+    /// Adds a sample.
+    pub fn add(&mut self, sample: Sample) {
+        let mut tags: Vec<(String, String)> = sample.tags.into_iter().collect();
+        tags.sort();
+        let key = (sample.metric, tags);
+        match sample.value {
+            Value::Counter(value) => *self.counters.entry(key).or_insert(0.0) += value / sample.sample_rate,
+            Value::Timer(value) => self.add_timing(key, value, sample.sample_rate, "ms"),
+            Value::Histogram(value) => self.add_timing(key, value, sample.sample_rate, "value"),
+            Value::Gauge(value) => {
+                self.gauges.insert(key, (value, true));
+            }
+            Value::GaugeDelta(delta) => {
+                let gauge = self.gauges.entry(key).or_insert((0.0, true));
+                *gauge = (gauge.0 + delta, true);
+            }
+            Value::Set(value) => {
+                self.sets.entry(key).or_default().insert(value);
+            }
+        }
+    }
+
+    fn add_timing(&mut self, key: Key, value: f64, sample_rate: f64, unit: &'static str) {
+        let timings = self.timings.entry(key).or_default();
+        timings.values.push(value);
+        timings.count += 1.0 / sample_rate;
+        timings.unit = unit;
+    }
+
+    /// Returns the aggregates of all samples added since the last flush sorted by metric and tags.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use std::time::Duration;
+    /// # use bosun_emitter::statsd::{self, Aggregator};
+    /// let mut aggregator = Aggregator::new(vec![90.0]);
+    /// for sample in statsd::parse_packet("logins:1|c\nlogins:1|c|@0.5\nlogin.time:10|ms\nlogin.time:30|ms").0 {
+    ///     aggregator.add(sample);
+    /// }
+    /// let aggregates = aggregator.flush(1458066838000, Duration::from_secs(10));
+    /// let data: Vec<(&str, &str)> = aggregates.iter()
+    ///     .map(|a| (a.data_point.metric.as_str(), a.data_point.value.as_str()))
+    ///     .collect();
+    ///
+    /// assert_eq!(data, vec![("login.time.count", "2"), ("login.time.max", "30"), ("login.time.mean", "20"),
+    ///                       ("login.time.min", "10"), ("login.time.p90", "30"), ("logins.count", "3"),
+    ///                       ("logins.rate", "0.3")]);
+    /// assert!(aggregator.flush(1458066848000, Duration::from_secs(10)).is_empty());
+    /// ```
+    pub fn flush(&mut self, timestamp: i64, interval: Duration) -> Vec<Aggregate> {
+        let seconds = interval.as_secs() as f64 + interval.subsec_nanos() as f64 / 1e9;
+        let mut aggregates = Vec::new();
+        {
+            let mut push = |key: &Key, suffix: &str, value: f64, rate, unit, description: String| {
+                let metric = if suffix.is_empty() { key.0.clone() } else { format!("{}.{}", key.0, suffix) };
+                let data_point = DataPoint::new(metric, timestamp, value.to_string(), key.1.iter().cloned().collect());
+                aggregates.push(Aggregate::new(data_point, rate, unit, description));
+            };
+
+            for (key, count) in self.counters.drain() {
+                push(&key, "count", count, "gauge", "count", "Events counted by StatsD per flush interval".to_string());
+                if seconds > 0.0 {
+                    push(&key, "rate", count / seconds, "rate", "per second", "Events counted by StatsD per second".to_string());
+                }
+            }
+            for (key, mut timings) in self.timings.drain() {
+                let kind = if timings.unit == "ms" { "timings" } else { "values" };
+                timings.values.sort_by(|a, b| a.partial_cmp(b).unwrap());
+                let values = &timings.values;
+                let sum: f64 = values.iter().sum();
+                push(&key, "count", timings.count, "gauge", "count",
+                     format!("Number of {} measured by StatsD per flush interval", kind));
+                push(&key, "min", values[0], "gauge", timings.unit, format!("Minimum of {} measured by StatsD", kind));
+                push(&key, "max", values[values.len() - 1], "gauge", timings.unit,
+                     format!("Maximum of {} measured by StatsD", kind));
+                push(&key, "mean", sum / values.len() as f64, "gauge", timings.unit,
+                     format!("Mean of {} measured by StatsD", kind));
+                for &percentile in &self.percentiles {
+                    // Nearest rank
+                    let rank = ((percentile / 100.0 * values.len() as f64).ceil() as usize).max(1).min(values.len());
+                    let suffix = format!("p{}", percentile.to_string().replace('.', "_"));
+                    push(&key, &suffix, values[rank - 1], "gauge", timings.unit,
+                         format!("{}th percentile of {} measured by StatsD", percentile, kind));
+                }
+            }
+            for (key, &mut (value, ref mut updated)) in &mut self.gauges {
+                if *updated {
+                    push(key, "", value, "gauge", "value", "Gauge set by StatsD".to_string());
+                    *updated = false;
+                }
+            }
+            for (key, values) in self.sets.drain() {
+                push(&key, "count", values.len() as f64, "gauge", "count",
+                     "Unique values counted by StatsD per flush interval".to_string());
+            }
+        }
+        aggregates.sort_by(|a, b| {
+            let tags = |a: &Aggregate| {
+                let mut tags: Vec<(String, String)> = a.data_point.tags.clone().into_iter().collect();
+                tags.sort();
+                tags
+            };
+            (&a.data_point.metric, tags(a)).cmp(&(&b.data_point.metric, tags(b)))
+        });
+
+        aggregates
+    }
+}
+
+/// Receives StatsD samples via UDP in the background and aggregates them.
+#[derive(Debug)]
+pub struct Listener {
+    aggregator: Arc<Mutex<Aggregator>>,
+    address: SocketAddr,
+}
+
+impl Listener {
+    /// Listens on a UDP address, e.g., `127.0.0.1:8125`, and computes the percentiles given of timers.
+    pub fn bind<A: ToSocketAddrs>(address: A, percentiles: Vec<f64>) -> io::Result<Listener> {
+        let socket = try!(UdpSocket::bind(address));
+        let address = try!(socket.local_addr());
+        let aggregator = Arc::new(Mutex::new(Aggregator::new(percentiles)));
+
+        let receiving = aggregator.clone();
+        thread::spawn(move || {
+            let mut buffer = [0u8; 65535];
+            loop {
+                let size = match socket.recv_from(&mut buffer) {
+                    Ok((size, _)) => size,
+                    Err(e) => {
+                        warn!("Failed to receive StatsD packet, because {}.", e);
+                        continue;
+                    }
+                };
+                let (samples, errors) = parse_packet(&String::from_utf8_lossy(&buffer[..size]));
+                for error in errors {
+                    debug!("Skipping malformed StatsD sample, because {}.", error);
+                }
+                let mut aggregator = receiving.lock().unwrap();
+                for sample in samples {
+                    aggregator.add(sample);
+                }
+            }
+        });
+
+        Ok(Listener {
+            aggregator: aggregator,
+            address: address,
+        })
+    }
+
+    /// Returns the address bound.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.address
+    }
+
+    /// Returns the aggregates of all samples received since the last flush, cf. `Aggregator::flush`.
+    pub fn flush(&self, timestamp: i64, interval: Duration) -> Vec<Aggregate> {
+        self.aggregator.lock().unwrap().flush(timestamp, interval)
+    }
+}
@@ -0,0 +1,186 @@
+//! Textfile collector directories like node_exporter's: cron jobs write their metrics into files
+//! of a directory, and each run reads and sends all of them.
+//!
+//! Files ending with `.prom` are read in Prometheus' text format, cf. `prometheus::parse`, so they
+//! declare their meta data themselves. All other files are read in the line formats the agent
+//! accepts, i.e., OpenTSDB's line format, JSON, or InfluxDB's line protocol, cf.
+//! `input::detect_format`; lines starting with `#` are comments.
+//!
+//! Writers should write to a temporary file and rename it, so files are always complete. Hidden
+//! files and files ending with `.tmp` or `~` are skipped, and a file changing while it is read is
+//! read again.
+//!
+//! # Example
+//!
+//! ```no_run
+//! use bosun_emitter::now_in_ms;
+//! use bosun_emitter::prometheus::LabelRules;
+//! use bosun_emitter::textfile::{self, TextFile};
+//!
+//! for path in textfile::files("/var/lib/bosun/textfile").unwrap() {
+//!     let file = TextFile::read(&path, &LabelRules::default()).unwrap();
+//!     println!("{}: {} samples, {} data points", path.display(), file.samples.len(), file.data_points.len());
+//!     for sample in file.metrics(now_in_ms()) {
+//!         println!("{} = {}", sample.data_point.metric, sample.data_point.value);
+//!     }
+//! }
+//! ```
+
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use super::{DataPoint, Tags, clean_tag_value, input, now_in_ms};
+use super::prometheus::{self, LabelRules, Sample};
+
+/// Default directory of text files
+pub static DEFAULT_TEXTFILE_DIR: &'static str = "/var/lib/bosun/textfile";
+
+/// How often a file changing while it is read is read again
+static READ_ATTEMPTS: usize = 3;
+
+/// Metrics describing each file: suffix, unit, and description
+pub static FILE_METRICS: &'static [(&'static str, &'static str, &'static str)] = &[
+    ("mtime", "timestamp", "Unix time in sec the text file has been modified last"),
+    ("age", "seconds", "Time since the text file has been modified last"),
+    ("errors", "count", "Malformed lines of the text file"),
+];
+
+/// Returns the files to read in alphabetical order; hidden and temporary files are skipped.
+pub fn files<P: AsRef<Path>>(dir: P) -> io::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for entry in try!(fs::read_dir(dir)) {
+        let entry = try!(entry);
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if name.starts_with('.') || name.ends_with(".tmp") || name.ends_with('~') {
+            continue;
+        }
+        if try!(entry.file_type()).is_file() {
+            files.push(entry.path());
+        }
+    }
+    files.sort();
+
+    Ok(files)
+}
+
+/// Contents of a text file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TextFile {
+    /// File read
+    pub path: PathBuf,
+    /// Unix timestamp in ms the file has been modified last
+    pub modified: i64,
+    /// Samples with meta data read from a `*.prom` file
+    pub samples: Vec<Sample>,
+    /// Data points read from a file in a line format
+    pub data_points: Vec<DataPoint>,
+    /// Malformed lines
+    pub errors: Vec<String>,
+}
+
+impl TextFile {
+    /// Reads and parses a file; samples without timestamp get the current time.
+    pub fn read<P: AsRef<Path>>(path: P, rules: &LabelRules) -> io::Result<TextFile> {
+        let path = path.as_ref();
+        let (text, modified) = try!(read_unchanged(path));
+        let mut file = TextFile {
+            path: path.to_path_buf(),
+            modified: modified,
+            samples: Vec::new(),
+            data_points: Vec::new(),
+            errors: Vec::new(),
+        };
+        if path.extension().map_or(false, |e| e == "prom") {
+            let (samples, errors) = prometheus::parse(&text, now_in_ms(), rules);
+            file.samples = samples;
+            file.errors = errors;
+        } else {
+            file.parse_lines(&text);
+        }
+
+        Ok(file)
+    }
+
+    fn parse_lines(&mut self, text: &str) {
+        for (index, line) in text.lines().enumerate() {
+            if line.trim_start().starts_with('#') {
+                continue;
+            }
+            match input::parse_line(input::detect_format(line), line) {
+                Ok(data) => self.data_points.extend(data),
+                Err(e) => self.errors.push(format!("line {}: {}", index + 1, e)),
+            }
+        }
+    }
+
+    /// Returns the metrics describing the file, i.e., `emit_bosun.textfile.mtime`, `.age`, and
+    /// `.errors` with the file name as tag `file`, to alert on jobs that stopped writing.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use std::path::PathBuf;
+    /// # use bosun_emitter::textfile::TextFile;
+    /// let file = TextFile {
+    ///     path: PathBuf::from("/var/lib/bosun/textfile/backup.prom"),
+    ///     modified: 1458066838000,
+    ///     samples: Vec::new(),
+    ///     data_points: Vec::new(),
+    ///     errors: vec!["line 3: missing value".to_string()],
+    /// };
+    /// let metrics = file.metrics(1458070438000);
+    ///
+    /// assert_eq!(metrics[0].data_point.metric, "emit_bosun.textfile.mtime");
+    /// assert_eq!(metrics[0].data_point.value, "1458066838");
+    /// assert_eq!(metrics[1].data_point.value, "3600");
+    /// assert_eq!(metrics[2].data_point.value, "1");
+    /// assert_eq!(metrics[2].data_point.tags["file"], "backup.prom");
+    /// ```
+    pub fn metrics(&self, timestamp: i64) -> Vec<Sample> {
+        let name = self.path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+        let mut tags = Tags::new();
+        tags.insert("file".to_string(), clean_tag_value(&name));
+        let values = [self.modified / 1000, (timestamp - self.modified).max(0) / 1000, self.errors.len() as i64];
+
+        FILE_METRICS.iter()
+            .zip(values.iter())
+            .map(|(&(suffix, unit, description), value)| {
+                Sample {
+                    data_point: DataPoint::new(format!("emit_bosun.textfile.{}", suffix), timestamp, value.to_string(), tags.clone()),
+                    rate: "gauge",
+                    unit: unit.to_string(),
+                    description: description.to_string(),
+                }
+            })
+            .collect()
+    }
+}
+
+/// Reads a file completely and returns its content and modification time in ms; reads it again if
+/// it changes meanwhile.
+fn read_unchanged(path: &Path) -> io::Result<(String, i64)> {
+    for _ in 0..READ_ATTEMPTS {
+        let before = try!(modified(path));
+        let mut text = String::new();
+        try!(File::open(path).and_then(|mut f| f.read_to_string(&mut text)));
+        if try!(modified(path)) == before {
+            return Ok((text, before.0));
+        }
+    }
+
+    Err(io::Error::new(io::ErrorKind::Other, "file keeps changing while being read"))
+}
+
+/// Returns the modification time in ms and the length of a file.
+fn modified(path: &Path) -> io::Result<(i64, u64)> {
+    let metadata = try!(fs::metadata(path));
+    let modified = try!(metadata.modified());
+    let ms = match modified.duration_since(UNIX_EPOCH) {
+        Ok(duration) => duration.as_secs() as i64 * 1000 + duration.subsec_nanos() as i64 / 1_000_000,
+        Err(_) => 0,
+    };
+
+    Ok((ms, metadata.len()))
+}